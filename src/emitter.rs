@@ -1,7 +1,12 @@
 #![allow(dead_code, unused_imports)]
 
+use crate::flow;
 use crate::parser::*;
+use crate::types::{self, IntType, Type};
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::error::Error;
+use std::fmt;
 
 // Emit C code based on the AST we have generated.
 //
@@ -14,83 +19,975 @@ use std::error::Error;
 // With these, we can move through the AST and generate C code.
 
 #[derive(Debug)]
-struct Symbol {
+struct Label {
     name: String,
-    value: i32,
+    used: bool,
+}
+
+// Every label defined in a list of statements, including those nested in blocks.
+pub fn defined_labels(statements: &[Statement]) -> Vec<&str> {
+    let mut labels = vec![];
+    for statement in statements {
+        match statement {
+            Statement::Label(ident) => labels.push(ident.as_str()),
+            Statement::If {
+                body,
+                else_ifs,
+                else_body,
+                ..
+            } => {
+                labels.extend(defined_labels(body));
+                for (_, body) in else_ifs {
+                    labels.extend(defined_labels(body));
+                }
+                if let Some(else_body) = else_body {
+                    labels.extend(defined_labels(else_body));
+                }
+            }
+            Statement::While { body, .. } | Statement::For { body, .. } => {
+                labels.extend(defined_labels(body))
+            }
+            Statement::Spanned(_, statement) => {
+                labels.extend(defined_labels(std::slice::from_ref(statement)))
+            }
+            _ => {}
+        }
+    }
+    labels
+}
+
+// Escape a lexed string so it can sit inside a C string literal. Anything
+// outside printable ASCII is written byte by byte as `\xNN`, so the C source
+// stays ASCII and the program prints the string's UTF-8 bytes unchanged.
+pub(crate) fn escape_string(string: &str) -> String {
+    let mut output = String::new();
+    let mut after_hex = false;
+    for byte in string.bytes() {
+        // A hex escape swallows every hex digit after it, so close the
+        // literal and let C join it back up with the next one
+        if after_hex && byte.is_ascii_hexdigit() {
+            output.push_str("\"\"");
+        }
+        after_hex = false;
+        match byte {
+            b'\n' => output.push_str("\\n"),
+            b'\t' => output.push_str("\\t"),
+            b'\\' => output.push_str("\\\\"),
+            b'"' => output.push_str("\\\""),
+            b' '..=b'~' => output.push(byte as char),
+            _ => {
+                output.push_str(&format!("\\x{:02x}", byte));
+                after_hex = true;
+            }
+        }
+    }
+    output
 }
 
+// An error from a statement that came with a span, saying which line it was on.
 #[derive(Debug)]
-struct Label {
-    name: String,
-    used: bool,
+pub struct LineError {
+    pub line: usize,
+    pub error: Box<dyn Error>,
 }
 
-fn print_unary(unary: Unary) -> String {
-    match unary {
-        Unary::Plus(primary) => match *primary {
-            Primary::Ident(ident) => format!("{}", ident),
-            Primary::Number(number) => format!("{}", number),
-        },
-        Unary::Minus(primary) => match *primary {
-            Primary::Ident(ident) => format!("-{}", ident),
-            Primary::Number(number) => format!("-{}", number),
-        },
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
     }
 }
 
-pub fn emit_program(statements: Vec<Statement>) -> Result<Vec<String>, Box<dyn Error>> {
-    let mut code_header: Vec<String> = Vec::new();
-    let mut code_body: Vec<String> = Vec::new();
+impl Error for LineError {}
 
-    code_header.push("#include <stdio.h>".to_string());
-    code_header.push("int main(void){\n".to_string());
+impl LineError {
+    // Put `line` on an error. Blocks are spanned too, so an error that already
+    // has a line keeps the innermost one.
+    pub(crate) fn wrap(line: usize, error: Box<dyn Error>) -> Box<dyn Error> {
+        if error.is::<LineError>() {
+            error
+        } else {
+            Box::new(LineError { line, error })
+        }
+    }
+}
 
-    for statement in statements {
+// One level of indentation in the generated C.
+const INDENT: &str = "    ";
+
+// A program starts running at the label of this name if it has one, wherever
+// in the file that is, and at the top otherwise.
+const ENTRY_LABEL: &str = "start";
+
+// The most an INPUT$ reads from one line, counting the terminating NUL.
+const INPUT_BUFFER_SIZE: usize = 256;
+
+// Keeps the declared variables and labels around between calls, so statements
+// can be emitted a few at a time (e.g. line by line from the REPL).
+#[derive(Debug, Default)]
+pub struct Emitter {
+    // The type of every variable, from `types::extend_types`
+    types: HashMap<String, Type>,
+    // Variables in the order they were first assigned
+    declared: Vec<String>,
+    labels: Vec<Label>,
+    // Set once a power is emitted, since `pow` needs <math.h>
+    uses_math: Cell<bool>,
+    // Set once two strings are compared, since `strcmp` needs <string.h>
+    compares_strings: Cell<bool>,
+    // Set once a checked +, - or * is emitted, so the helpers get defined
+    checks_arith: Cell<bool>,
+    // Set once RANDOM is emitted, so main seeds `rand` from the clock
+    uses_random: Cell<bool>,
+    // Set once ABS is emitted, since it needs <stdlib.h>
+    uses_abs: Cell<bool>,
+    // Set once MIN or MAX is emitted, so the helpers get defined
+    uses_min_max: Cell<bool>,
+    // How many temporaries comparison chains have needed, each declared as
+    // `teeny_chain<n>`
+    chain_temps: Cell<usize>,
+    // Every variable an INPUT$ reads into, each of which gets a buffer
+    // declared as `teeny_input_<name>`
+    input_buffers: Vec<String>,
+    // Set once an EXIT with a code is emitted, since `exit` needs <stdlib.h>
+    exits_with_code: bool,
+    // How many WHILE and FOR loops enclose the statement being emitted
+    loop_depth: usize,
+    // How many levels in the lines being emitted are indented
+    indent: usize,
+    // Headers asked for with #include, in the order they first appeared
+    includes: Vec<String>,
+    // Each CONST's name and C value, which become #defines at the top
+    constants: Vec<(String, String)>,
+    options: EmitOptions,
+}
+
+// Choices about the C that gets generated.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EmitOptions {
+    pub int_type: IntType,
+    // Do +, - and * through helpers that abort on overflow, rather than
+    // leaving it undefined
+    pub checked_arith: bool,
+    // Mark each statement that has a span with a `/* line N */` comment
+    pub line_comments: bool,
+}
+
+// The helper that does a checked `operator`, if it is one that gets checked.
+fn checked_helper(operator: &str) -> Option<&'static str> {
+    match operator {
+        "+" => Some("teeny_add"),
+        "-" => Some("teeny_sub"),
+        "*" => Some("teeny_mul"),
+        _ => None,
+    }
+}
+
+// Definitions of the checked arithmetic helpers for `int_type`, which use the
+// GCC and Clang overflow builtins.
+fn checked_arith_helpers(int_type: IntType) -> Vec<String> {
+    let c_type = int_type.c_type();
+    let mut lines = vec![];
+    for (operator, builtin) in [("+", "add"), ("-", "sub"), ("*", "mul")] {
+        lines.extend([
+            format!(
+                "static {} {}({} a, {} b) {{",
+                c_type,
+                checked_helper(operator).unwrap(),
+                c_type,
+                c_type
+            ),
+            format!("{}{} result;", INDENT, c_type),
+            format!(
+                "{}if (__builtin_{}_overflow(a, b, &result)) {{",
+                INDENT, builtin
+            ),
+            format!(
+                "{}{}fprintf(stderr, \"integer overflow in {}\\n\");",
+                INDENT, INDENT, operator
+            ),
+            format!("{}{}abort();", INDENT, INDENT),
+            format!("{}}}", INDENT),
+            format!("{}return result;", INDENT),
+            "}".to_string(),
+        ]);
+    }
+    lines
+}
+
+// Whether evaluating `expression` draws a random number, so evaluating it
+// twice could give two different values.
+fn calls_random(expression: &Expression) -> bool {
+    fn primary_calls_random(primary: &Primary) -> bool {
+        match primary {
+            Primary::Builtin(Builtin::Random, _) => true,
+            Primary::Builtin(_, arguments) => arguments.iter().any(calls_random),
+            Primary::Group(expression) | Primary::Index(_, expression) => calls_random(expression),
+            Primary::Power(base, exponent) => {
+                let (Unary::None(exponent)
+                | Unary::Plus(exponent)
+                | Unary::Minus(exponent)
+                | Unary::BitNot(exponent)) = exponent.as_ref();
+                primary_calls_random(base) || primary_calls_random(exponent)
+            }
+            Primary::Number(_) | Primary::Ident(_) | Primary::String(_) => false,
+        }
+    }
+    types::primaries(expression)
+        .into_iter()
+        .any(primary_calls_random)
+}
+
+// C has no min or max for integers, and a macro would evaluate its arguments
+// twice (calling RANDOM twice, say), so these get defined as functions.
+fn min_max_helpers(int_type: IntType) -> Vec<String> {
+    let c_type = int_type.c_type();
+    let mut lines = vec![];
+    for (name, operator) in [("min", "<"), ("max", ">")] {
+        lines.extend([
+            format!(
+                "static {} teeny_{}({} a, {} b) {{",
+                c_type, name, c_type, c_type
+            ),
+            format!("{}return a {} b ? a : b;", INDENT, operator),
+            "}".to_string(),
+        ]);
+    }
+    lines
+}
+
+impl Emitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: EmitOptions) -> Self {
+        Emitter {
+            options,
+            ..Self::default()
+        }
+    }
+
+    // Declare `ident` the first time it is assigned. Its type has already
+    // been worked out, so all that is left is to remember the order.
+    fn declare(&mut self, ident: &str) {
+        if !self.declared.iter().any(|name| name == ident) {
+            self.declared.push(ident.to_string());
+        }
+    }
+
+    // A CONST can only be given its value once.
+    fn check_assignable(&self, ident: &str) -> Result<(), Box<dyn Error>> {
+        if self.constants.iter().any(|(name, _)| name == ident) {
+            return Err(format!("cannot assign to constant '{}'", ident).into());
+        }
+        Ok(())
+    }
+
+    // The #define for every CONST, in the order they were declared.
+    pub fn defines(&self) -> Vec<String> {
+        self.constants
+            .iter()
+            .map(|(name, value)| format!("#define {} {}", name, value))
+            .collect()
+    }
+
+    // Record every label in `statements` up front, so a duplicate is reported
+    // before any code is emitted. Nothing is recorded if there is a duplicate.
+    fn define_labels(&mut self, statements: &[Statement]) -> Result<(), Box<dyn Error>> {
+        let mut labels: Vec<Label> = vec![];
+        for name in defined_labels(statements) {
+            let defined = |label: &Label| label.name == name;
+            if self.labels.iter().any(defined) || labels.iter().any(defined) {
+                return Err(format!("label '{}' defined more than once", name).into());
+            }
+            labels.push(Label {
+                name: name.to_string(),
+                used: false,
+            });
+        }
+        self.labels.append(&mut labels);
+        Ok(())
+    }
+
+    // Things that are suspicious but still compile, such as labels no GOTO
+    // ever jumps to.
+    pub fn warnings(&self) -> Vec<String> {
+        self.labels
+            .iter()
+            .filter(|label| !label.used)
+            .map(|label| format!("label '{}' is never used", label.name))
+            .collect()
+    }
+
+    fn expression_type(&self, expression: &Expression) -> Result<Type, Box<dyn Error>> {
+        types::expression_type(&self.types, expression)
+    }
+
+    // Every variable is declared once at the top of main, in the order it was
+    // first assigned, so a GOTO can never jump over a declaration.
+    pub fn declarations(&self) -> Vec<String> {
+        self.declared
+            .iter()
+            .map(|name| {
+                let ty = self.types[name];
+                let c_type = ty.c_type(self.options.int_type);
+                match ty {
+                    Type::String => format!("{}{};", c_type, name),
+                    Type::Int => format!("{} {};", c_type, name),
+                    Type::Array(size) => format!("{} {}[{}] = {{0}};", c_type, name, size),
+                }
+            })
+            .chain(
+                self.input_buffers
+                    .iter()
+                    .map(|name| format!("char teeny_input_{}[{}];", name, INPUT_BUFFER_SIZE)),
+            )
+            .chain(
+                (0..self.chain_temps.get()).map(|index| {
+                    format!("{} teeny_chain{};", self.options.int_type.c_type(), index)
+                }),
+            )
+            .collect()
+    }
+
+    fn emit_primary(&self, primary: &Primary) -> Result<String, Box<dyn Error>> {
+        match primary {
+            Primary::Ident(ident) => Ok(ident.to_string()),
+            // -2147483648 in C is the negation of a long, so spell out an int
+            Primary::Number(i32::MIN) => Ok(format!("({} - 1)", i32::MIN + 1)),
+            Primary::Number(number) => Ok(number.to_string()),
+            Primary::Index(ident, index) => {
+                Ok(format!("{}[{}]", ident, self.emit_expression(index)?))
+            }
+            Primary::String(string) => Ok(format!("\"{}\"", escape_string(string))),
+            Primary::Group(expression) => Ok(format!("({})", self.emit_expression(expression)?)),
+            // `pow` works on doubles, so bring the result back to an integer
+            Primary::Power(base, exponent) => {
+                self.uses_math.set(true);
+                Ok(format!(
+                    "({})pow({}, {})",
+                    self.options.int_type.c_type(),
+                    self.emit_primary(base)?,
+                    self.emit_unary(exponent)?
+                ))
+            }
+            Primary::Builtin(Builtin::Random, arguments) => {
+                self.uses_random.set(true);
+                Ok(format!(
+                    "(rand() % {})",
+                    self.emit_expression(&arguments[0])?
+                ))
+            }
+            Primary::Builtin(Builtin::Abs, arguments) => {
+                self.uses_abs.set(true);
+                Ok(format!(
+                    "{}({})",
+                    self.options.int_type.abs_function(),
+                    self.emit_expression(&arguments[0])?
+                ))
+            }
+            Primary::Builtin(function @ (Builtin::Min | Builtin::Max), arguments) => {
+                self.uses_min_max.set(true);
+                Ok(format!(
+                    "teeny_{}({}, {})",
+                    function.name(),
+                    self.emit_expression(&arguments[0])?,
+                    self.emit_expression(&arguments[1])?
+                ))
+            }
+        }
+    }
+
+    // `left operator right`, or under --checked-arith a call to the helper
+    // that checks it, if there is one.
+    fn emit_arithmetic(&self, left: &str, operator: &str, right: &str) -> String {
+        match checked_helper(operator).filter(|_| self.options.checked_arith) {
+            Some(helper) => {
+                self.checks_arith.set(true);
+                format!("{}({}, {})", helper, left, right)
+            }
+            None => format!("{} {} {}", left, operator, right),
+        }
+    }
+
+    // The operands of a & or | chain, parenthesized as a whole.
+    fn emit_operands(
+        &self,
+        operands: &[Expression],
+        operator: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        Ok(format!(
+            "({})",
+            operands
+                .iter()
+                .map(|operand| self.emit_expression(operand))
+                .collect::<Result<Vec<String>, Box<dyn Error>>>()?
+                .join(operator)
+        ))
+    }
+
+    fn emit_unary(&self, unary: &Unary) -> Result<String, Box<dyn Error>> {
+        match unary {
+            Unary::None(primary) | Unary::Plus(primary) => self.emit_primary(primary),
+            Unary::Minus(primary) => Ok(format!("-{}", self.emit_primary(primary)?)),
+            Unary::BitNot(primary) => Ok(format!("~{}", self.emit_primary(primary)?)),
+        }
+    }
+
+    fn emit_term(&self, term: &Term) -> Result<String, Box<dyn Error>> {
+        match term {
+            Term::SingleUnary(unary) => self.emit_unary(unary),
+            Term::WithTail(unary, tail) => {
+                let TermTail::Tail(tailunaries) = tail.as_ref();
+                let mut output = self.emit_unary(unary)?;
+                for tailunary in tailunaries {
+                    let (operator, unary) = match tailunary {
+                        TailUnary::Multiply(unary) => ("*", unary),
+                        TailUnary::Divide(unary) => ("/", unary),
+                        TailUnary::Modulo(unary) => ("%", unary),
+                    };
+                    output = self.emit_arithmetic(&output, operator, &self.emit_unary(unary)?);
+                }
+                Ok(output)
+            }
+        }
+    }
+
+    // Lower an expression to a C infix string, keeping the left-to-right order of the tails.
+    fn emit_expression(&self, expression: &Expression) -> Result<String, Box<dyn Error>> {
+        match expression {
+            Expression::SingleTerm(term) => self.emit_term(term),
+            Expression::WithTail(term, tail) => {
+                let ExpressionTail::Tail(tailterms) = tail.as_ref();
+                let mut output = self.emit_term(term)?;
+                for tailterm in tailterms {
+                    let (operator, term) = match tailterm {
+                        TailTerm::Add(term) => ("+", term),
+                        TailTerm::Subtract(term) => ("-", term),
+                    };
+                    output = self.emit_arithmetic(&output, operator, &self.emit_term(term)?);
+                }
+                Ok(output)
+            }
+            // C's & and | bind looser than comparisons, so keep them parenthesized
+            Expression::BitAnd(operands) => self.emit_operands(operands, " & "),
+            Expression::BitOr(operands) => self.emit_operands(operands, " | "),
+        }
+    }
+
+    // Every comparison comes back wrapped in its own parentheses, e.g.
+    // `(a != b)`, so it can go straight into an `if` or `while` and can be
+    // combined with `&&`, `||` and `!` without worrying about precedence.
+    // Comparisons joined by a logical operator, parenthesized as a whole.
+    fn emit_comparisons(
+        &self,
+        comparisons: &[Comparison],
+        operator: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        Ok(format!(
+            "({})",
+            comparisons
+                .iter()
+                .map(|comparison| self.emit_comparison(comparison))
+                .collect::<Result<Vec<String>, Box<dyn Error>>>()?
+                .join(operator)
+        ))
+    }
+
+    // The links of `a < b < c` joined with &&. C would evaluate `b` once for
+    // each link it is in, so a `b` that draws a random number is stored in a
+    // temporary by the first link and read back by the second.
+    fn emit_chain(&self, comparisons: &[Comparison]) -> Result<String, Box<dyn Error>> {
+        let mut links = vec![];
+        // The temporary holding this link's left operand, if there is one
+        let mut shared: Option<String> = None;
+        for (index, comparison) in comparisons.iter().enumerate() {
+            let Some((left, operator, right)) = comparison.relation() else {
+                links.push(self.emit_comparison(comparison)?);
+                continue;
+            };
+            let stores_right = calls_random(right)
+                && comparisons
+                    .get(index + 1)
+                    .and_then(Comparison::relation)
+                    .is_some_and(|(next_left, _, _)| next_left == right);
+            if shared.is_none() && !stores_right {
+                links.push(self.emit_comparison(comparison)?);
+                continue;
+            }
+            let left = match shared.take() {
+                Some(temp) => temp,
+                None => self.emit_expression(left)?,
+            };
+            let right = if stores_right {
+                let temp = format!("teeny_chain{}", self.chain_temps.get());
+                self.chain_temps.set(self.chain_temps.get() + 1);
+                let value = format!("({} = {})", temp, self.emit_expression(right)?);
+                shared = Some(temp);
+                value
+            } else {
+                self.emit_expression(right)?
+            };
+            links.push(format!("({} {} {})", left, operator, right));
+        }
+        Ok(format!("({})", links.join(" && ")))
+    }
+
+    fn emit_comparison(&self, comparison: &Comparison) -> Result<String, Box<dyn Error>> {
+        let (left, operator, right) = match comparison {
+            Comparison::Chain(comparisons) => return self.emit_chain(comparisons),
+            Comparison::And(comparisons) => return self.emit_comparisons(comparisons, " && "),
+            Comparison::Or(comparisons) => return self.emit_comparisons(comparisons, " || "),
+            Comparison::Not(comparison) => {
+                return Ok(format!("(!{})", self.emit_comparison(comparison)?))
+            }
+            // The types pass only lets strings through to == and !=, and only
+            // against another string
+            Comparison::Equal(left, right) | Comparison::NotEqual(left, right)
+                if self.expression_type(left)? == Type::String =>
+            {
+                self.compares_strings.set(true);
+                let operator = match comparison {
+                    Comparison::Equal(..) => "==",
+                    _ => "!=",
+                };
+                return Ok(format!(
+                    "(strcmp({}, {}) {} 0)",
+                    self.emit_expression(left)?,
+                    self.emit_expression(right)?,
+                    operator
+                ));
+            }
+            Comparison::Equal(left, right) => (left, "==", right),
+            Comparison::NotEqual(left, right) => (left, "!=", right),
+            Comparison::GreaterThan(left, right) => (left, ">", right),
+            Comparison::GreaterThanEqual(left, right) => (left, ">=", right),
+            Comparison::LessThan(left, right) => (left, "<", right),
+            Comparison::LessThanEqual(left, right) => (left, "<=", right),
+        };
+        Ok(format!(
+            "({} {} {})",
+            self.emit_expression(left)?,
+            operator,
+            self.emit_expression(right)?
+        ))
+    }
+
+    fn emit_print(&self, printable: &Printable, newline: bool) -> Result<String, Box<dyn Error>> {
+        let end = if newline { "\\n" } else { "" };
+        Ok(match printable {
+            Printable::String(string) => format!(
+                "printf(\"{}{}\");",
+                escape_string(string).replace('%', "%%"),
+                end
+            ),
+            Printable::Expression(expression) => format!(
+                "printf(\"{}{}\", {});",
+                self.expression_type(expression)?
+                    .format_specifier(self.options.int_type),
+                end,
+                self.emit_expression(expression)?
+            ),
+            Printable::Comparison(comparison) => format!(
+                "printf(\"%d{}\", {});",
+                end,
+                self.emit_comparison(comparison)?
+            ),
+        })
+    }
+
+    fn emit_statement(
+        &mut self,
+        statement: Statement,
+        code_body: &mut Vec<String>,
+    ) -> Result<(), Box<dyn Error>> {
         match statement {
-            Statement::PrintString(string) => code_body.push(format!("printf(\"{}\\n\");", string)),
-            Statement::PrintExpression(expression) => match *expression {
-                Expression::SingleTerm(term) => match *term {
-                    Term::SingleUnary(unary) => match *unary {
-                        Unary::Plus(primary) => match *primary {
-                            Primary::Ident(ident) => {
-                                code_body.push(format!("printf(\"%d\\n\", {});", ident))
-                            }
-                            Primary::Number(number) => {
-                                code_body.push(format!("printf(\"%d\\n\", {});", number))
-                            }
-                        },
-                        Unary::Minus(primary) => match *primary {
-                            Primary::Ident(ident) => {
-                                code_body.push(format!("printf(\"%d\\n\", -{});", ident))
-                            }
-                            Primary::Number(number) => {
-                                code_body.push(format!("printf(\"%d\\n\", -{});", number))
-                            }
-                        },
-                    },
-                    Term::WithTail(_unary, _tailunaries) => {}
-                },
-                Expression::WithTail(_term, _tailterms) => {
-
-                    code_body.push("/* unimplemented expression with tail */".to_string())
+            Statement::PrintString(string) => self.push_line(
+                code_body,
+                self.emit_print(&Printable::String(string), true)?,
+            ),
+            Statement::PrintExpression(expression) => self.push_line(
+                code_body,
+                self.emit_print(&Printable::Expression(expression), true)?,
+            ),
+            Statement::PrintComparison(comparison) => self.push_line(
+                code_body,
+                self.emit_print(&Printable::Comparison(comparison), true)?,
+            ),
+            Statement::Printn(printable) => {
+                self.push_line(code_body, self.emit_print(&printable, false)?)
+            }
+            Statement::If {
+                comparison,
+                body,
+                else_ifs,
+                else_body,
+            } => {
+                self.push_line(
+                    code_body,
+                    format!("if {} {{", self.emit_comparison(&comparison)?),
+                );
+                self.emit_block(body, code_body)?;
+                for (comparison, body) in else_ifs {
+                    self.push_line(
+                        code_body,
+                        format!("}} else if {} {{", self.emit_comparison(&comparison)?),
+                    );
+                    self.emit_block(body, code_body)?;
                 }
-            },
-            Statement::If { comparison: _, body: _ } => {
-                code_body.push("/* unimplemented if statement */".to_string())
+                if let Some(else_body) = else_body {
+                    self.push_line(code_body, "} else {".to_string());
+                    self.emit_block(else_body, code_body)?;
+                }
+                self.push_line(code_body, "}".to_string());
+            }
+            Statement::While { comparison, body } => {
+                self.push_line(
+                    code_body,
+                    format!("while {} {{", self.emit_comparison(&comparison)?),
+                );
+                self.emit_loop_body(body, code_body)?;
+                self.push_line(code_body, "}".to_string());
+            }
+            Statement::For {
+                ident,
+                start,
+                end,
+                body,
+            } => {
+                self.check_assignable(&ident)?;
+                let start = self.emit_expression(&start)?;
+                self.declare(&ident);
+                self.push_line(
+                    code_body,
+                    format!(
+                        "for ({} = {}; {} <= {}; {}++) {{",
+                        ident,
+                        start,
+                        ident,
+                        self.emit_expression(&end)?,
+                        ident
+                    ),
+                );
+                self.emit_loop_body(body, code_body)?;
+                self.push_line(code_body, "}".to_string());
+            }
+            Statement::Break | Statement::Continue if self.loop_depth == 0 => {
+                let keyword = if statement == Statement::Break {
+                    "BREAK"
+                } else {
+                    "CONTINUE"
+                };
+                return Err(format!("{} outside of a loop", keyword).into());
+            }
+            Statement::Break => self.push_line(code_body, "break;".to_string()),
+            Statement::Exit(None) => self.push_line(code_body, "return 0;".to_string()),
+            Statement::Exit(Some(code)) => {
+                self.exits_with_code = true;
+                let code = self.emit_expression(&code)?;
+                self.push_line(code_body, format!("exit({});", code));
+            }
+            Statement::Continue => self.push_line(code_body, "continue;".to_string()),
+            Statement::Label(ident) => self.push_line(code_body, format!("{}:;", ident)),
+            Statement::Goto(ident) => {
+                if let Some(label) = self.labels.iter_mut().find(|label| label.name == ident) {
+                    label.used = true;
+                }
+                self.push_line(code_body, format!("goto {};", ident));
+            }
+            Statement::Let { ident, expression } => {
+                self.check_assignable(&ident)?;
+                let value = self.emit_expression(&expression)?;
+                self.declare(&ident);
+                self.push_line(code_body, format!("{} = {};", ident, value));
+            }
+            Statement::LetComparison { ident, comparison } => {
+                self.check_assignable(&ident)?;
+                let value = self.emit_comparison(&comparison)?;
+                self.declare(&ident);
+                self.push_line(code_body, format!("{} = {};", ident, value));
+            }
+            Statement::MultiLet(assignments) => {
+                for (ident, expression) in assignments {
+                    self.check_assignable(&ident)?;
+                    let value = self.emit_expression(&expression)?;
+                    self.declare(&ident);
+                    self.push_line(code_body, format!("{} = {};", ident, value));
+                }
+            }
+            // A negative number is bracketed so `x - n` can't become `x - -5`
+            // or worse once the preprocessor has pasted it in
+            Statement::Const { ident, value } => {
+                self.check_assignable(&ident)?;
+                let value = match value {
+                    Primary::Number(number) if number < 0 => {
+                        format!("({})", self.emit_primary(&value)?)
+                    }
+                    _ => self.emit_primary(&value)?,
+                };
+                self.constants.push((ident, value));
+            }
+            // The declaration at the top of main is all the C an array needs
+            Statement::ArrayDecl { ident, .. } => self.declare(&ident),
+            Statement::IndexAssign {
+                ident,
+                index,
+                expression,
+            } => {
+                self.push_line(
+                    code_body,
+                    format!(
+                        "{} = {};",
+                        self.emit_primary(&Primary::Index(ident, Box::new(index)))?,
+                        self.emit_expression(&expression)?
+                    ),
+                );
+            }
+            Statement::CompoundAssign {
+                ident,
+                op,
+                expression,
+            } => {
+                self.check_assignable(&ident)?;
+                let value = self.emit_expression(&expression)?;
+                let operator = match op {
+                    AssignOp::Add => "+",
+                    AssignOp::Subtract => "-",
+                    AssignOp::Multiply => "*",
+                    AssignOp::Divide => "/",
+                };
+                let line = match checked_helper(operator).filter(|_| self.options.checked_arith) {
+                    Some(_) => format!(
+                        "{} = {};",
+                        ident,
+                        self.emit_arithmetic(&ident, operator, &value)
+                    ),
+                    None => format!("{} {} {};", ident, op, value),
+                };
+                self.push_line(code_body, line);
             }
-            Statement::While { comparison: _, body: _ } => {
-                code_body.push("/* unimplemented while statement */".to_string())
+            // Headers all go at the top of the file, so there is nothing to emit here
+            Statement::Include(header) => {
+                if !self.includes.contains(&header) {
+                    self.includes.push(header);
+                }
+            }
+            // Whatever the function returns is thrown away
+            Statement::Call { name, arguments } => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.emit_expression(argument))
+                    .collect::<Result<Vec<String>, Box<dyn Error>>>()?;
+                self.push_line(code_body, format!("{}({});", name, arguments.join(", ")));
+            }
+            // The types pass has already checked `ident` holds an int or a string
+            Statement::Input(ident) => {
+                self.check_assignable(&ident)?;
+                match self.types.get(&ident) {
+                    Some(Type::String) => self.emit_read_line(&ident, code_body),
+                    _ => self.push_line(
+                        code_body,
+                        format!(
+                            "scanf(\"{}\", &{});",
+                            self.options.int_type.scanf_specifier(),
+                            ident
+                        ),
+                    ),
+                }
             }
-            Statement::Label(_ident) => code_body.push("/* unimplemented label */".to_string()),
-            Statement::Goto(_ident) => code_body.push("/* unimplemented goto */".to_string()),
-            Statement::Let { ident: _, expression: _ } => {
-                code_body.push("/* unimplemented let */".to_string())
+            Statement::Dump(ident) => {
+                let ty = types::primary_type(&self.types, &Primary::Ident(ident.clone()))?;
+                self.push_line(
+                    code_body,
+                    format!(
+                        "printf(\"{} = {}\\n\", {});",
+                        ident,
+                        ty.format_specifier(self.options.int_type),
+                        ident
+                    ),
+                );
             }
-            Statement::Input(_ident) => code_body.push("/* unimplemented input */".to_string()),
+            Statement::InputString(ident) => {
+                self.check_assignable(&ident)?;
+                self.declare(&ident);
+                self.emit_read_line(&ident, code_body);
+            }
+            Statement::Spanned(span, statement) => {
+                if self.options.line_comments {
+                    self.push_line(code_body, format!("/* line {} */", span.line));
+                }
+                self.emit_statement(*statement, code_body)
+                    .map_err(|error| LineError::wrap(span.line, error))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Each variable reads into its own buffer, declared once, so an INPUT$ in
+    // a loop doesn't allocate every time round. Another variable that was
+    // assigned from it sees the new line too. Longer lines are cut short.
+    fn emit_read_line(&mut self, ident: &str, code_body: &mut Vec<String>) {
+        if !self.input_buffers.iter().any(|name| name == ident) {
+            self.input_buffers.push(ident.to_string());
+        }
+        self.push_line(code_body, format!("{} = teeny_input_{};", ident, ident));
+        self.push_line(
+            code_body,
+            format!(
+                "if (!fgets({}, {}, stdin)) {}[0] = '\\0';",
+                ident, INPUT_BUFFER_SIZE, ident
+            ),
+        );
+        self.push_line(
+            code_body,
+            format!("{}[strcspn({}, \"\\n\")] = '\\0';", ident, ident),
+        );
+    }
+
+    // Add a line of C at the current indentation.
+    fn push_line(&self, code_body: &mut Vec<String>, line: String) {
+        code_body.push(format!("{}{}", INDENT.repeat(self.indent), line));
+    }
+
+    // The statements inside an IF, WHILE or FOR, one level further in. The
+    // indentation is restored even on error, since the REPL keeps the emitter.
+    fn emit_block(
+        &mut self,
+        body: Vec<Statement>,
+        code_body: &mut Vec<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.indent += 1;
+        let result = body
+            .into_iter()
+            .try_for_each(|statement| self.emit_statement(statement, code_body));
+        self.indent -= 1;
+        result
+    }
+
+    // The body of a WHILE or FOR, where BREAK and CONTINUE are allowed.
+    fn emit_loop_body(
+        &mut self,
+        body: Vec<Statement>,
+        code_body: &mut Vec<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.loop_depth += 1;
+        let result = self.emit_block(body, code_body);
+        self.loop_depth -= 1;
+        result
+    }
+
+    pub fn emit_statements(
+        &mut self,
+        statements: Vec<Statement>,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        self.define_labels(&statements)?;
+        // Work on a copy, so statements that fail leave no variables behind
+        let mut types = self.types.clone();
+        types::extend_types(&mut types, &statements)?;
+        self.types = types;
+        let mut code_body: Vec<String> = Vec::new();
+        for statement in statements {
+            self.emit_statement(statement, &mut code_body)?;
+        }
+        Ok(code_body)
+    }
+}
+
+// A whole C program, ready to be written out, and anything suspicious the
+// emitter noticed along the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmittedProgram {
+    pub source: String,
+    pub warnings: Vec<String>,
+}
+
+pub fn emit_program(statements: Vec<Statement>) -> Result<EmittedProgram, Box<dyn Error>> {
+    emit_program_with_options(statements, EmitOptions::default())
+}
+
+pub fn emit_program_with_options(
+    statements: Vec<Statement>,
+    options: EmitOptions,
+) -> Result<EmittedProgram, Box<dyn Error>> {
+    let (lines, warnings) = emit_lines(statements, options)?;
+    let mut source = lines.join("\n");
+    source.push('\n');
+    Ok(EmittedProgram { source, warnings })
+}
+
+// The program as separate lines, along with the emitter's warnings.
+fn emit_lines(
+    statements: Vec<Statement>,
+    options: EmitOptions,
+) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+    let mut emitter = Emitter::with_options(options);
+    // Everything in main sits one level in
+    emitter.indent = 1;
+    let mut first = statements.first();
+    while let Some(Statement::Spanned(_, statement)) = first {
+        first = Some(statement);
+    }
+    let jump_to_entry = defined_labels(&statements).contains(&ENTRY_LABEL)
+        && !matches!(first, Some(Statement::Label(label)) if label == ENTRY_LABEL);
+    let skipped_assignments = flow::skipped_assignments(&statements);
+    let mut code_body = emitter.emit_statements(statements)?;
+    if jump_to_entry {
+        code_body.insert(0, format!("{}goto {};", INDENT, ENTRY_LABEL));
+        for label in emitter.labels.iter_mut() {
+            label.used |= label.name == ENTRY_LABEL;
         }
     }
 
-    code_body.push("return 0;".to_string());
+    // stdio first, then any the program asked for, then any the generated
+    // code needs, each only once
+    let mut headers = vec!["<stdio.h>".to_string()];
+    headers.extend(options.int_type.header().map(str::to_string));
+    headers.extend(emitter.includes.iter().cloned());
+    if emitter.uses_math.get() {
+        headers.push("<math.h>".to_string());
+    }
+    if !emitter.input_buffers.is_empty() {
+        headers.push("<string.h>".to_string());
+    }
+    if emitter.compares_strings.get() {
+        headers.push("<string.h>".to_string());
+    }
+    if emitter.checks_arith.get() {
+        headers.push("<stdlib.h>".to_string());
+    }
+    if emitter.uses_random.get() {
+        headers.push("<stdlib.h>".to_string());
+        headers.push("<time.h>".to_string());
+    }
+    if emitter.uses_abs.get() || emitter.exits_with_code {
+        headers.push("<stdlib.h>".to_string());
+    }
+    let mut code_header: Vec<String> = Vec::new();
+    for header in headers {
+        let include = format!("#include {}", header);
+        if !code_header.contains(&include) {
+            code_header.push(include);
+        }
+    }
+    code_header.extend(emitter.defines());
+    if emitter.checks_arith.get() {
+        code_header.extend(checked_arith_helpers(options.int_type));
+    }
+    if emitter.uses_min_max.get() {
+        code_header.extend(min_max_helpers(options.int_type));
+    }
+    code_header.push("int main(void){\n".to_string());
+    code_header.extend(
+        emitter
+            .declarations()
+            .into_iter()
+            .map(|declaration| format!("{}{}", INDENT, declaration)),
+    );
+    if emitter.uses_random.get() {
+        code_header.push(format!("{}srand(time(NULL));", INDENT));
+    }
+
+    code_body.push(format!("{}return 0;", INDENT));
     code_body.push("}".to_string());
 
     let mut output: Vec<String> = Vec::new();
@@ -98,16 +995,34 @@ pub fn emit_program(statements: Vec<Statement>) -> Result<Vec<String>, Box<dyn E
     output.append(&mut code_header);
     output.append(&mut code_body);
 
-    Ok(output)
+    let mut warnings = emitter.warnings();
+    warnings.extend(skipped_assignments);
+    Ok((output, warnings))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexer::{lex, lex_with_spans, TokenIterator};
+
+    // The lines of a program, with the one level of indentation everything
+    // inside main has taken off, so nested blocks show up as indented.
+    fn emit_program_lines_with(statements: Vec<Statement>, options: EmitOptions) -> Vec<String> {
+        let (lines, _warnings) = emit_lines(statements, options).unwrap();
+        lines
+            .iter()
+            .map(|line| line.strip_prefix(INDENT).unwrap_or(line).to_string())
+            .collect()
+    }
+
+    fn emit_program_lines(statements: Vec<Statement>) -> Vec<String> {
+        emit_program_lines_with(statements, EmitOptions::default())
+    }
+
     #[test]
     fn test_emit_program() {
         let ast = vec![Statement::PrintString("waddup".to_string())];
-        let result = emit_program(ast).unwrap();
+        let result = emit_program_lines(ast);
         assert_eq!(
             result,
             vec![
@@ -119,4 +1034,1090 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_emit_let() {
+        let ast = vec![
+            Statement::Let {
+                ident: "x".to_string(),
+                expression: Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(
+                    Unary::None(Box::new(Primary::Number(5))),
+                )))),
+            },
+            Statement::Let {
+                ident: "x".to_string(),
+                expression: Expression::WithTail(
+                    Box::new(Term::SingleUnary(Box::new(Unary::None(Box::new(
+                        Primary::Ident("x".to_string()),
+                    ))))),
+                    Box::new(ExpressionTail::Tail(vec![TailTerm::Add(Box::new(
+                        Term::SingleUnary(Box::new(Unary::None(Box::new(Primary::Number(1))))),
+                    ))])),
+                ),
+            },
+        ];
+        let result = emit_program_lines(ast);
+        assert_eq!(
+            result[2..5],
+            [
+                "int x;".to_string(),
+                "x = 5;".to_string(),
+                "x = x + 1;".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_if() {
+        let input = "if 1 == 1 then\nprint 1\nendif";
+        let tokens = lex(input).unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+        let result = emit_program_lines(statements);
+        assert_eq!(
+            result[2..5],
+            [
+                "if (1 == 1) {".to_string(),
+                "    printf(\"%d\\n\", 1);".to_string(),
+                "}".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_nested_if() {
+        let input = "if 1 == 1 then\nif 2 != 3 then\nprint 1\nendif\nendif";
+        let tokens = lex(input).unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+        let result = emit_program_lines(statements);
+        assert_eq!(
+            result[2..7],
+            [
+                "if (1 == 1) {".to_string(),
+                "    if (2 != 3) {".to_string(),
+                "        printf(\"%d\\n\", 1);".to_string(),
+                "    }".to_string(),
+                "}".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_while() {
+        let input = "let x = 0\nwhile x < 10 repeat\nlet x = x + 1\nendwhile";
+        let tokens = lex(input).unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+        let result = emit_program_lines(statements);
+        assert_eq!(
+            result[2..7],
+            [
+                "int x;".to_string(),
+                "x = 0;".to_string(),
+                "while (x < 10) {".to_string(),
+                "    x = x + 1;".to_string(),
+                "}".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_nested_while() {
+        let input = "while 1 == 1 repeat\nwhile 2 == 2 repeat\nif 3 == 3 then\nprint 3\nendif\nendwhile\nendwhile";
+        let tokens = lex(input).unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+        let result = emit_program_lines(statements);
+        assert_eq!(
+            result[2..9],
+            [
+                "while (1 == 1) {".to_string(),
+                "    while (2 == 2) {".to_string(),
+                "        if (3 == 3) {".to_string(),
+                "            printf(\"%d\\n\", 3);".to_string(),
+                "        }".to_string(),
+                "    }".to_string(),
+                "}".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_input() {
+        assert_eq!(
+            emit_source("let x = 0\ninput x\ninput x")[2..6],
+            [
+                "int x;",
+                "x = 0;",
+                "scanf(\"%d\", &x);",
+                "scanf(\"%d\", &x);"
+            ]
+        );
+
+        // A string variable reads a whole line, just like INPUT$
+        assert_eq!(
+            emit_source("let s = \"hi\"\ninput s")[3..9],
+            [
+                "char *s;",
+                "char teeny_input_s[256];",
+                "s = \"hi\";",
+                "s = teeny_input_s;",
+                "if (!fgets(s, 256, stdin)) s[0] = '\\0';",
+                "s[strcspn(s, \"\\n\")] = '\\0';",
+            ]
+        );
+
+        let tokens = lex("if 1 == 1 then\ninput x\nendif").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap_err().to_string(),
+            "INPUT into undeclared variable 'x'"
+        );
+    }
+
+    fn emit_source(input: &str) -> Vec<String> {
+        let tokens = lex(input).unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+        emit_program_lines(statements)
+    }
+
+    #[test]
+    fn test_emit_print_term_tail() {
+        assert_eq!(emit_source("print 2 * 3")[2], "printf(\"%d\\n\", 2 * 3);");
+        assert_eq!(
+            emit_source("print 2 * 3 / 4")[2],
+            "printf(\"%d\\n\", 2 * 3 / 4);"
+        );
+        assert_eq!(
+            emit_source("print 1 + 2 * 3 / 4")[2],
+            "printf(\"%d\\n\", 1 + 2 * 3 / 4);"
+        );
+    }
+
+    #[test]
+    fn test_emit_print_expression_tail() {
+        assert_eq!(
+            emit_source("print 1 + 2 - 3")[2],
+            "printf(\"%d\\n\", 1 + 2 - 3);"
+        );
+        assert_eq!(emit_source("print -1 + 2")[2], "printf(\"%d\\n\", -1 + 2);");
+        assert_eq!(
+            emit_source("let x = 1\nprint 4 - -x * 2")[4],
+            "printf(\"%d\\n\", 4 - -x * 2);"
+        );
+    }
+
+    #[test]
+    fn test_emit_chained_comparison() {
+        assert_eq!(
+            emit_source("let x = 2\nif 1 < x <= 3 then\nendif")[4],
+            "if ((1 < x) && (x <= 3)) {"
+        );
+    }
+
+    #[test]
+    fn test_emit_string_escapes() {
+        assert_eq!(
+            emit_source(r#"print "say \"hi\"\t100%\\""#)[2],
+            r#"printf("say \"hi\"\t100%%\\\n");"#
+        );
+    }
+
+    #[test]
+    fn test_emit_string_bytes() {
+        assert_eq!(
+            emit_source("print \"a\tcaf\u{e9}!\"")[2],
+            r#"printf("a\tcaf\xc3\xa9!\n");"#
+        );
+        // The digit after an escape must not become part of it
+        assert_eq!(escape_string("\u{1}2\u{1}g"), r#"\x01""2\x01g"#);
+    }
+
+    #[test]
+    fn test_emit_if_else() {
+        let result = emit_source("if 1 == 2 then\nprint 1\nelse\nprint 2\nendif");
+        assert_eq!(
+            result[2..7],
+            [
+                "if (1 == 2) {".to_string(),
+                "    printf(\"%d\\n\", 1);".to_string(),
+                "} else {".to_string(),
+                "    printf(\"%d\\n\", 2);".to_string(),
+                "}".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_for() {
+        let result = emit_source("for i = 1 to 10\nprint i\nendfor");
+        assert_eq!(
+            result[2..6],
+            [
+                "int i;".to_string(),
+                "for (i = 1; i <= 10; i++) {".to_string(),
+                "    printf(\"%d\\n\", i);".to_string(),
+                "}".to_string(),
+            ]
+        );
+
+        let result = emit_source("let i = 0\nfor i = i to 3\nendfor");
+        assert_eq!(result[4], "for (i = i; i <= 3; i++) {");
+    }
+
+    #[test]
+    fn test_emit_modulo() {
+        assert_eq!(emit_source("print 7 % 3")[2], "printf(\"%d\\n\", 7 % 3);");
+    }
+
+    #[test]
+    fn test_emit_and_or() {
+        assert_eq!(
+            emit_source("let x = 0\nif x > 0 and x < 10 or x == 20 then\nendif")[4],
+            "if (((x > 0) && (x < 10)) || (x == 20)) {"
+        );
+        assert_eq!(
+            emit_source(
+                "let x = 0\nlet y = 0\ninput x\ninput y\nif x > 0 and x < 10 or x == 20 and y == 1 then\nendif"
+            )[8],
+            "if (((x > 0) && (x < 10)) || ((x == 20) && (y == 1))) {"
+        );
+    }
+
+    #[test]
+    fn test_emit_not() {
+        assert_eq!(
+            emit_source("let x = 0\nif not x == 1 then\nendif")[4],
+            "if (!(x == 1)) {"
+        );
+        assert_eq!(
+            emit_source(
+                "let x = 0\nlet y = 0\nlet z = 0\nif not x == 1 or not y < 2 and z > 3 then\nendif"
+            )[8],
+            "if ((!(x == 1)) || ((!(y < 2)) && (z > 3))) {"
+        );
+    }
+
+    #[test]
+    fn test_emitter_keeps_symbols() {
+        let mut emitter = Emitter::new();
+        let tokens = lex("let x = 1").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(emitter.emit_statements(statements).unwrap(), vec!["x = 1;"]);
+        let tokens = lex("print x").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emitter.emit_statements(statements).unwrap(),
+            vec!["printf(\"%d\\n\", x);"]
+        );
+        assert_eq!(emitter.declarations(), vec!["int x;"]);
+    }
+
+    #[test]
+    fn test_emit_undeclared_variable() {
+        let tokens = lex("let y = x + 1").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let error = emit_program(statements).unwrap_err();
+        assert_eq!(error.to_string(), "use of undeclared variable 'x'");
+
+        let tokens = lex("if 1 == 1 then\nprint x\nendif").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert!(emit_program(statements).is_err());
+    }
+
+    #[test]
+    fn test_emit_hoisted_declarations() {
+        let result = emit_source(
+            "print 1\nlet x = 1\nif x == 1 then\nlet y = 0\ninput y\nendif\nfor i = 1 to y\nlet x = i\nendfor",
+        );
+        assert_eq!(
+            result[..5],
+            [
+                "#include <stdio.h>".to_string(),
+                "int main(void){\n".to_string(),
+                "int x;".to_string(),
+                "int y;".to_string(),
+                "int i;".to_string(),
+            ]
+        );
+        assert_eq!(
+            result
+                .iter()
+                .filter(|line| line.starts_with("int "))
+                .count(),
+            4
+        );
+        assert_eq!(result[5], "printf(\"%d\\n\", 1);");
+    }
+
+    #[test]
+    fn test_emit_string_variable() {
+        let result = emit_source("let name = \"teeny\"\nprint name\nlet other = name");
+        assert_eq!(
+            result[2..],
+            [
+                "char *name;".to_string(),
+                "char *other;".to_string(),
+                "name = \"teeny\";".to_string(),
+                "printf(\"%s\\n\", name);".to_string(),
+                "other = name;".to_string(),
+                "return 0;".to_string(),
+                "}".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_string_type_errors() {
+        for input in [
+            "let name = \"teeny\"\nprint name + 1",
+            "let name = \"teeny\"\nprint -name",
+            "let name = \"teeny\"\nlet name = 1",
+            "let x = 1\nlet x = \"teeny\"",
+            "let name = \"teeny\"\nif name == 1 then\nendif",
+        ] {
+            let tokens = lex(input).unwrap();
+            let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            assert!(emit_program(statements).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_emit_print_comparison() {
+        let result = emit_source("let x = 1\nprint x == 1\nprint not x * 2 > 1 and x < 3");
+        assert_eq!(result[4], "printf(\"%d\\n\", (x == 1));");
+        assert_eq!(result[5], "printf(\"%d\\n\", ((!(x * 2 > 1)) && (x < 3)));");
+    }
+
+    #[test]
+    fn test_emit_group() {
+        assert_eq!(
+            emit_source("print (1 + 2) * 3")[2],
+            "printf(\"%d\\n\", (1 + 2) * 3);"
+        );
+        assert_eq!(
+            emit_source("print -(1 - (2 - 3))")[2],
+            "printf(\"%d\\n\", -(1 - (2 - 3)));"
+        );
+    }
+
+    #[test]
+    fn test_emit_label_goto() {
+        let result = emit_source("label top\nprint 1\ngoto top");
+        assert_eq!(
+            result[2..5],
+            ["top:;", "printf(\"%d\\n\", 1);", "goto top;"]
+        );
+
+        let tokens = lex("label a\nif 1 == 1 then\nlabel b\nelse\nlabel c\nendif\ngoto a").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(defined_labels(&statements), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_emit_duplicate_label() {
+        let tokens = lex("label main\nprint 1\nif 1 == 1 then\nlabel main\nendif").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap_err().to_string(),
+            "label 'main' defined more than once"
+        );
+
+        // Labels stay defined between calls on the same emitter
+        let mut emitter = Emitter::new();
+        assert!(emitter
+            .emit_statements(vec![Statement::Label("a".to_string())])
+            .is_ok());
+        assert!(emitter
+            .emit_statements(vec![Statement::Label("a".to_string())])
+            .is_err());
+    }
+
+    #[test]
+    fn test_emit_unused_label_warning() {
+        let tokens = lex("label start\nlabel unused\nprint 1\ngoto start").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let (output, warnings) = emit_lines(statements, EmitOptions::default()).unwrap();
+        let output = output
+            .iter()
+            .map(|line| line.trim_start())
+            .collect::<Vec<_>>();
+        assert_eq!(output[3], "unused:;");
+        assert_eq!(warnings, ["label 'unused' is never used"]);
+    }
+
+    #[test]
+    fn test_emit_entry_label() {
+        let tokens =
+            lex("label helper\nprint 1\ngoto done\nlabel start\nprint 2\ngoto helper\nlabel done")
+                .unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let (output, warnings) = emit_lines(statements, EmitOptions::default()).unwrap();
+        assert_eq!(
+            output[1..4],
+            ["int main(void){\n", "    goto start;", "    helper:;"]
+        );
+        assert!(warnings.is_empty());
+
+        // Nothing to jump over when the program already starts there
+        assert_eq!(
+            emit_source("label start\nprint 1")[2..4],
+            ["start:;", "printf(\"%d\\n\", 1);"]
+        );
+        let tokens = lex("print 1\nif 1 == 1 then\nlabel start\nendif").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(emit_program_lines(statements)[2], "goto start;");
+    }
+
+    #[test]
+    fn test_emit_power() {
+        let result = emit_source("print 2 ^ 10");
+        assert_eq!(result[0..2], ["#include <stdio.h>", "#include <math.h>"]);
+        assert_eq!(result[3], "printf(\"%d\\n\", (int)pow(2, 10));");
+
+        let result = emit_source("let x = 3\nprint -x ^ (1 + 1) ^ 2 * 2");
+        assert_eq!(
+            result[5],
+            "printf(\"%d\\n\", -(int)pow(x, (int)pow((1 + 1), 2)) * 2);"
+        );
+
+        let tokens = lex("let s = \"a\"\nprint 2 ^ s").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert!(emit_program(statements).is_err());
+    }
+
+    #[test]
+    fn test_emit_bitwise() {
+        assert_eq!(emit_source("print 5 & 3")[2], "printf(\"%d\\n\", (5 & 3));");
+        assert_eq!(emit_source("print ~0")[2], "printf(\"%d\\n\", ~0);");
+        assert_eq!(
+            emit_source("if 1 | 2 & 3 == 3 then\nendif")[2],
+            "if ((1 | (2 & 3)) == 3) {"
+        );
+    }
+
+    #[test]
+    fn test_emit_line_comments() {
+        let (tokens, spans) =
+            lex_with_spans("let x = 1\n\nif x == 1 then\nprint x\nendif").unwrap();
+        let AST::Program(statements) =
+            parse_with_spans(&mut TokenIterator::new(&tokens), &spans).unwrap();
+        let options = EmitOptions {
+            line_comments: true,
+            ..EmitOptions::default()
+        };
+        let result = emit_program_lines_with(statements.clone(), options);
+        // Spans alone only label errors
+        assert!(!emit_program_lines(statements)
+            .iter()
+            .any(|line| line.contains("/* line")));
+        assert_eq!(
+            result[3..9],
+            [
+                "/* line 1 */",
+                "x = 1;",
+                "/* line 3 */",
+                "if (x == 1) {",
+                "    /* line 4 */",
+                "    printf(\"%d\\n\", x);"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_printn() {
+        let result = emit_source("printn \"x = \"\nprintn 42\nprintn 1 < 2\nprint \"\"");
+        assert_eq!(
+            result[2..6],
+            [
+                "printf(\"x = \");",
+                "printf(\"%d\", 42);",
+                "printf(\"%d\", (1 < 2));",
+                "printf(\"\\n\");"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_elseif() {
+        let result = emit_source(
+            "let x = 0\ninput x\nif x < 0 then\nprint \"negative\"\nelseif x == 0 then\nprint \"zero\"\nelse\nprint \"positive\"\nendif",
+        );
+        assert_eq!(
+            result[5..12],
+            [
+                "if (x < 0) {",
+                "    printf(\"negative\\n\");",
+                "} else if (x == 0) {",
+                "    printf(\"zero\\n\");",
+                "} else {",
+                "    printf(\"positive\\n\");",
+                "}"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_comparison_operators() {
+        for operator in ["==", "!=", ">", ">=", "<", "<="] {
+            let output = emit_source(&format!(
+                "let x = 1\nlet y = 2\nif x + 1 {0} y * 2 then\nendif\nwhile x {0} (y - 1) % 3 repeat\nendwhile",
+                operator
+            ));
+            assert_eq!(output[6], format!("if (x + 1 {} y * 2) {{", operator));
+            assert_eq!(output[8], format!("while (x {} (y - 1) % 3) {{", operator));
+        }
+    }
+
+    #[test]
+    fn test_emit_input_string() {
+        let output = emit_source("input$ name\nprint name");
+        assert_eq!(
+            output,
+            [
+                "#include <stdio.h>",
+                "#include <string.h>",
+                "int main(void){\n",
+                "char *name;",
+                "char teeny_input_name[256];",
+                "name = teeny_input_name;",
+                "if (!fgets(name, 256, stdin)) name[0] = '\\0';",
+                "name[strcspn(name, \"\\n\")] = '\\0';",
+                "printf(\"%s\\n\", name);",
+                "return 0;",
+                "}",
+            ]
+        );
+
+        // The buffer is declared once, however many times it is read into
+        let output = emit_source(
+            "let i = 0\nwhile i < 3 repeat\ninput$ name\nlet i = i + 1\nendwhile\ninput$ name",
+        );
+        assert_eq!(
+            output
+                .iter()
+                .filter(|line| line.starts_with("char teeny_input_name[256];"))
+                .count(),
+            1
+        );
+        assert_eq!(
+            output
+                .iter()
+                .filter(|line| line.trim() == "name = teeny_input_name;")
+                .count(),
+            2
+        );
+
+        let tokens = lex("let x = 1\ninput$ x").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap_err().to_string(),
+            "cannot assign string value to integer variable 'x'"
+        );
+    }
+
+    #[test]
+    fn test_emit_compound_assign() {
+        assert_eq!(
+            emit_source("let x = 1\nx += 5\nx -= 2\nx *= x + 1\nx /= 2")[4..8],
+            ["x += 5;", "x -= 2;", "x *= x + 1;", "x /= 2;"]
+        );
+
+        for (input, message) in [
+            ("x += 5", "use of undeclared variable 'x'"),
+            (
+                "let s = \"a\"\ns += 1",
+                "cannot use += on string variable 's'",
+            ),
+            (
+                "let x = 1\nlet s = \"a\"\nx += s",
+                "expected an integer expression but found a string",
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            assert_eq!(emit_program(statements).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_emit_array() {
+        let output = emit_source("let a = array 10\nlet i = 1\na[i * 2] = i + 1\nprint a[2]");
+        assert_eq!(
+            output[2..],
+            [
+                "int a[10] = {0};",
+                "int i;",
+                "i = 1;",
+                "a[i * 2] = i + 1;",
+                "printf(\"%d\\n\", a[2]);",
+                "return 0;",
+                "}",
+            ]
+        );
+
+        for (input, message) in [
+            (
+                "let a = array 3\nprint a",
+                "cannot use array 'a' without an index",
+            ),
+            ("let a = 1\nprint a[0]", "'a' is not an array"),
+            ("b[0] = 1", "use of undeclared variable 'b'"),
+            (
+                "let a = array 3\nlet a = array 4",
+                "'a' is already declared",
+            ),
+            (
+                "let a = array 3\nlet a = 1",
+                "cannot assign integer value to array variable 'a'",
+            ),
+            (
+                "let a = array 3\na[\"x\"] = 1",
+                "array index must be an integer",
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            assert_eq!(emit_program(statements).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_emit_break_continue() {
+        let output = emit_source(
+            "let x = 0\nwhile x < 10 repeat\nlet x = x + 1\nif x == 3 then continue\nif x == 5 then break\nendwhile",
+        );
+        assert_eq!(
+            output[5..13],
+            [
+                "    x = x + 1;",
+                "    if (x == 3) {",
+                "        continue;",
+                "    }",
+                "    if (x == 5) {",
+                "        break;",
+                "    }",
+                "}",
+            ]
+        );
+        assert_eq!(
+            emit_source("for i = 1 to 3\nbreak\nendfor")[4],
+            "    break;"
+        );
+
+        for (input, message) in [
+            ("break", "BREAK outside of a loop"),
+            ("if 1 == 1 then continue", "CONTINUE outside of a loop"),
+            (
+                "while 1 == 1 repeat\nendwhile\nbreak",
+                "BREAK outside of a loop",
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            assert_eq!(emit_program(statements).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_emit_call() {
+        let output = emit_source("let x = 2\ncall foo(1, x * 3, \"hi\")\ncall bar()");
+        assert_eq!(output[4..6], ["foo(1, x * 3, \"hi\");", "bar();"]);
+
+        let tokens = lex("call foo(y)").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap_err().to_string(),
+            "use of undeclared variable 'y'"
+        );
+    }
+
+    #[test]
+    fn test_emit_include() {
+        let output = emit_source(
+            "#include <math.h>\n#include \"lib.h\"\n#include <math.h>\nprint 2 ^ 3\ncall f()",
+        );
+        assert_eq!(
+            output[..4],
+            [
+                "#include <stdio.h>",
+                "#include <math.h>",
+                "#include \"lib.h\"",
+                "int main(void){\n",
+            ]
+        );
+        assert_eq!(
+            output
+                .iter()
+                .filter(|line| *line == "#include <math.h>")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_emit_int_type() {
+        let emit = |int_type| {
+            let tokens =
+                lex("let x = 0\ninput x\nlet a = array 2\nprint x ^ 2\nprint x < 1").unwrap();
+            let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            let options = EmitOptions {
+                int_type,
+                ..EmitOptions::default()
+            };
+            emit_program_lines_with(statements, options)
+        };
+
+        assert_eq!(
+            emit(IntType::Long)[3..],
+            [
+                "long x;",
+                "long a[2] = {0};",
+                "x = 0;",
+                "scanf(\"%ld\", &x);",
+                "printf(\"%ld\\n\", (long)pow(x, 2));",
+                "printf(\"%d\\n\", (x < 1));",
+                "return 0;",
+                "}",
+            ]
+        );
+
+        let output = emit(IntType::Int64);
+        assert_eq!(
+            output[..3],
+            [
+                "#include <stdio.h>",
+                "#include <inttypes.h>",
+                "#include <math.h>"
+            ]
+        );
+        assert_eq!(output[4], "int64_t x;");
+        assert_eq!(output[7], "scanf(\"%\" SCNd64 \"\", &x);");
+        assert_eq!(
+            output[8],
+            "printf(\"%\" PRId64 \"\\n\", (int64_t)pow(x, 2));"
+        );
+    }
+
+    #[test]
+    fn test_emit_let_comparison() {
+        let output =
+            emit_source("let x = 3\nlet y = 1\nlet flag = x > 0\nlet flag = x > 0 and y < 5");
+        assert_eq!(output[7], "flag = (x > 0);");
+        assert_eq!(output[8], "flag = ((x > 0) && (y < 5));");
+
+        let tokens = lex("let s = \"a\"\nlet s = s == \"b\"").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap_err().to_string(),
+            "cannot assign integer value to string variable 's'"
+        );
+    }
+
+    #[test]
+    fn test_emit_exit() {
+        assert_eq!(
+            emit_source("print 1\nexit\nprint 2"),
+            [
+                "#include <stdio.h>",
+                "int main(void){\n",
+                "printf(\"%d\\n\", 1);",
+                "return 0;",
+                "printf(\"%d\\n\", 2);",
+                "return 0;",
+                "}",
+            ]
+        );
+        let output = emit_source("let x = 2\nif x > 1 then exit 1\nexit x - 2");
+        assert_eq!(output[..2], ["#include <stdio.h>", "#include <stdlib.h>"]);
+        assert_eq!(output[6], "    exit(1);");
+        assert_eq!(output[8], "exit(x - 2);");
+        assert_eq!(output[9..], ["return 0;", "}"]);
+    }
+
+    #[test]
+    fn test_emit_multi_let() {
+        let output = emit_source("let x = 1, s = \"hi\", y = x + 1\nprint y");
+        assert_eq!(
+            output[2..9],
+            [
+                "int x;",
+                "char *s;",
+                "int y;",
+                "x = 1;",
+                "s = \"hi\";",
+                "y = x + 1;",
+                "printf(\"%d\\n\", y);",
+            ]
+        );
+
+        let tokens = lex("let x = y, y = 1").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap_err().to_string(),
+            "use of undeclared variable 'y'"
+        );
+    }
+
+    #[test]
+    fn test_emit_const() {
+        let output =
+            emit_source("const x = 5\nconst y = -1\nconst s = \"hi\"\nprint x - y\nprint s");
+        assert_eq!(
+            output[..6],
+            [
+                "#include <stdio.h>",
+                "#define x 5",
+                "#define y (-1)",
+                "#define s \"hi\"",
+                "int main(void){\n",
+                "printf(\"%d\\n\", x - y);",
+            ]
+        );
+        assert!(!output.iter().any(|line| line.starts_with("int x")));
+
+        for input in [
+            "const x = 5\nlet x = 6",
+            "const x = 5\nconst x = 6",
+            "const x = 5\nx += 1",
+            "const x = 5\ninput x",
+            "const x = 5\nfor x = 1 to 3\nendfor",
+        ] {
+            let tokens = lex(input).unwrap();
+            let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            assert_eq!(
+                emit_program(statements).unwrap_err().to_string(),
+                "cannot assign to constant 'x'",
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_emit_string_comparison() {
+        let output = emit_source(
+            "let a = \"x\"\nlet b = \"y\"\nif a == b then\nprint 1\nendif\nprint a != \"z\"",
+        );
+        assert_eq!(
+            output[..3],
+            [
+                "#include <stdio.h>",
+                "#include <string.h>",
+                "int main(void){\n"
+            ]
+        );
+        assert_eq!(output[7], "if (strcmp(a, b) == 0) {");
+        assert_eq!(output[10], "printf(\"%d\\n\", (strcmp(a, \"z\") != 0));");
+
+        let tokens = lex("let a = \"x\"\nprint a < \"y\"").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap_err().to_string(),
+            "only == and != can compare strings"
+        );
+    }
+
+    #[test]
+    fn test_emit_random() {
+        let output = emit_source("let x = random(6)\nprint random(x) + 1");
+        assert_eq!(
+            output[..6],
+            [
+                "#include <stdio.h>",
+                "#include <stdlib.h>",
+                "#include <time.h>",
+                "int main(void){\n",
+                "int x;",
+                "srand(time(NULL));",
+            ]
+        );
+        assert_eq!(output[6], "x = (rand() % 6);");
+        assert_eq!(output[7], "printf(\"%d\\n\", (rand() % x) + 1);");
+
+        let tokens = lex("print random(\"six\")").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap_err().to_string(),
+            "random takes integer arguments"
+        );
+    }
+
+    #[test]
+    fn test_emit_random_in_chain() {
+        let output = emit_source("let x = 1 < random(6) < 4\nprint 0 < random(3) <= random(3) < 2");
+        assert_eq!(
+            output[4..9],
+            [
+                "int x;",
+                "int teeny_chain0;",
+                "int teeny_chain1;",
+                "int teeny_chain2;",
+                "srand(time(NULL));",
+            ]
+        );
+        assert_eq!(
+            output[9],
+            "x = ((1 < (teeny_chain0 = (rand() % 6))) && (teeny_chain0 < 4));"
+        );
+        assert_eq!(
+            output[10],
+            "printf(\"%d\\n\", ((0 < (teeny_chain1 = (rand() % 3))) && \
+             (teeny_chain1 <= (teeny_chain2 = (rand() % 3))) && (teeny_chain2 < 2)));"
+        );
+
+        // Operands that give the same value each time are left alone
+        let output = emit_source("let x = 2\nprint 1 < x + 1 < 4");
+        assert_eq!(
+            output[4],
+            "printf(\"%d\\n\", ((1 < x + 1) && (x + 1 < 4)));"
+        );
+    }
+
+    #[test]
+    fn test_emit_abs_min_max() {
+        let output = emit_source("let x = abs(0 - 5)\nprint min(x, 3)\nprint max(x, random(2))");
+        assert_eq!(
+            output[..13],
+            [
+                "#include <stdio.h>",
+                "#include <stdlib.h>",
+                "#include <time.h>",
+                "static int teeny_min(int a, int b) {",
+                "return a < b ? a : b;",
+                "}",
+                "static int teeny_max(int a, int b) {",
+                "return a > b ? a : b;",
+                "}",
+                "int main(void){\n",
+                "int x;",
+                "srand(time(NULL));",
+                "x = abs(0 - 5);",
+            ]
+        );
+        assert_eq!(output[13], "printf(\"%d\\n\", teeny_min(x, 3));");
+        assert_eq!(output[14], "printf(\"%d\\n\", teeny_max(x, (rand() % 2)));");
+
+        let tokens = lex("let x = abs(0 - 5)").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let options = EmitOptions {
+            int_type: IntType::Long,
+            ..EmitOptions::default()
+        };
+        let output = emit_program_lines_with(statements, options);
+        assert_eq!(output[..2], ["#include <stdio.h>", "#include <stdlib.h>"]);
+        assert_eq!(output[4], "x = labs(0 - 5);");
+    }
+
+    #[test]
+    fn test_emit_checked_arith() {
+        let tokens =
+            lex("let a = 1\nlet b = 2\nprint a + b\nprint a - b * 2 / b % 3\na += b\na /= b")
+                .unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let options = EmitOptions {
+            checked_arith: true,
+            ..EmitOptions::default()
+        };
+        let (output, _) = emit_lines(statements, options).unwrap();
+        assert_eq!(output[..2], ["#include <stdio.h>", "#include <stdlib.h>"]);
+        assert_eq!(
+            output[2..10],
+            [
+                "static int teeny_add(int a, int b) {",
+                "    int result;",
+                "    if (__builtin_add_overflow(a, b, &result)) {",
+                "        fprintf(stderr, \"integer overflow in +\\n\");",
+                "        abort();",
+                "    }",
+                "    return result;",
+                "}",
+            ]
+        );
+        assert!(output.contains(&"static int teeny_sub(int a, int b) {".to_string()));
+        assert!(output.contains(&"static int teeny_mul(int a, int b) {".to_string()));
+        let body: Vec<&str> = output[output.len() - 6..]
+            .iter()
+            .map(|line| line.trim_start())
+            .collect();
+        assert_eq!(
+            body,
+            [
+                "printf(\"%d\\n\", teeny_add(a, b));",
+                "printf(\"%d\\n\", teeny_sub(a, teeny_mul(b, 2) / b % 3));",
+                "a = teeny_add(a, b);",
+                "a /= b;",
+                "return 0;",
+                "}",
+            ]
+        );
+
+        // Without any arithmetic there is nothing to define
+        let (output, _) =
+            emit_lines(vec![Statement::PrintString("hi".to_string())], options).unwrap();
+        assert_eq!(output[1], "int main(void){\n");
+    }
+
+    #[test]
+    fn test_emit_dump() {
+        let output = emit_source("let x = 3\nlet s = \"hi\"\ndump x\ndump s");
+        assert_eq!(
+            output[6..8],
+            ["printf(\"x = %d\\n\", x);", "printf(\"s = %s\\n\", s);"]
+        );
+
+        for (input, message) in [
+            ("dump y", "use of undeclared variable 'y'"),
+            (
+                "let a = array 2\ndump a",
+                "cannot use array 'a' without an index",
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            assert_eq!(emit_program(statements).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_emitted_program() {
+        let tokens = lex("label unused\nprint 1").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let program = emit_program(statements).unwrap();
+        assert_eq!(
+            program,
+            EmittedProgram {
+                source: "#include <stdio.h>\nint main(void){\n\n    unused:;\n    printf(\"%d\\n\", 1);\n    return 0;\n}\n"
+                    .to_string(),
+                warnings: vec!["label 'unused' is never used".to_string()],
+            }
+        );
+        assert!(program.source.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_emit_indentation() {
+        let tokens =
+            lex("let x = 0\nwhile x < 3 repeat\nwhile x < 2 repeat\nx += 1\nendwhile\nendwhile")
+                .unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            emit_program(statements).unwrap().source,
+            "#include <stdio.h>
+int main(void){
+
+    int x;
+    x = 0;
+    while (x < 3) {
+        while (x < 2) {
+            x += 1;
+        }
+    }
+    return 0;
+}
+"
+        );
+    }
 }
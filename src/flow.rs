@@ -0,0 +1,296 @@
+use crate::parser::*;
+
+// Checks on the order statements can run in, now that GOTO lets a program
+// jump around. Everything here only produces warnings: what it finds is
+// suspicious, but still valid C.
+
+// Every variable read in an expression.
+fn expression_reads(expression: &Expression) -> Vec<&str> {
+    fn primary_reads(primary: &Primary) -> Vec<&str> {
+        match primary {
+            Primary::Number(_) | Primary::String(_) => vec![],
+            Primary::Ident(ident) => vec![ident.as_str()],
+            // Arrays start out zeroed, so only the index matters
+            Primary::Index(_, index) => expression_reads(index),
+            Primary::Group(expression) => expression_reads(expression),
+            Primary::Builtin(_, arguments) => arguments.iter().flat_map(expression_reads).collect(),
+            Primary::Power(base, exponent) => {
+                let mut reads = primary_reads(base);
+                reads.extend(unary_reads(exponent));
+                reads
+            }
+        }
+    }
+    fn unary_reads(unary: &Unary) -> Vec<&str> {
+        match unary {
+            Unary::None(primary)
+            | Unary::Plus(primary)
+            | Unary::Minus(primary)
+            | Unary::BitNot(primary) => primary_reads(primary),
+        }
+    }
+    fn term_reads(term: &Term) -> Vec<&str> {
+        match term {
+            Term::SingleUnary(unary) => unary_reads(unary),
+            Term::WithTail(unary, tail) => {
+                let TermTail::Tail(tailunaries) = tail.as_ref();
+                let mut reads = unary_reads(unary);
+                for tailunary in tailunaries {
+                    let (TailUnary::Multiply(unary)
+                    | TailUnary::Divide(unary)
+                    | TailUnary::Modulo(unary)) = tailunary;
+                    reads.extend(unary_reads(unary));
+                }
+                reads
+            }
+        }
+    }
+    match expression {
+        Expression::SingleTerm(term) => term_reads(term),
+        Expression::WithTail(term, tail) => {
+            let ExpressionTail::Tail(tailterms) = tail.as_ref();
+            let mut reads = term_reads(term);
+            for tailterm in tailterms {
+                let (TailTerm::Add(term) | TailTerm::Subtract(term)) = tailterm;
+                reads.extend(term_reads(term));
+            }
+            reads
+        }
+        Expression::BitAnd(operands) | Expression::BitOr(operands) => {
+            operands.iter().flat_map(expression_reads).collect()
+        }
+    }
+}
+
+fn comparison_reads(comparison: &Comparison) -> Vec<&str> {
+    match comparison {
+        Comparison::Equal(left, right)
+        | Comparison::NotEqual(left, right)
+        | Comparison::GreaterThan(left, right)
+        | Comparison::GreaterThanEqual(left, right)
+        | Comparison::LessThan(left, right)
+        | Comparison::LessThanEqual(left, right) => {
+            let mut reads = expression_reads(left);
+            reads.extend(expression_reads(right));
+            reads
+        }
+        Comparison::Chain(comparisons)
+        | Comparison::And(comparisons)
+        | Comparison::Or(comparisons) => comparisons.iter().flat_map(comparison_reads).collect(),
+        Comparison::Not(comparison) => comparison_reads(comparison),
+    }
+}
+
+// The lists of statements nested directly inside a statement.
+fn bodies(statement: &Statement) -> Vec<&[Statement]> {
+    match statement {
+        Statement::If {
+            body,
+            else_ifs,
+            else_body,
+            ..
+        } => {
+            let mut bodies = vec![body.as_slice()];
+            bodies.extend(else_ifs.iter().map(|(_, body)| body.as_slice()));
+            bodies.extend(else_body.as_deref());
+            bodies
+        }
+        Statement::While { body, .. } | Statement::For { body, .. } => vec![body],
+        Statement::Spanned(_, statement) => bodies(statement),
+        _ => vec![],
+    }
+}
+
+// Every variable a statement reads, including in any blocks inside it.
+fn reads(statement: &Statement) -> Vec<&str> {
+    let mut found = match statement {
+        Statement::PrintExpression(expression) => expression_reads(expression),
+        Statement::PrintComparison(comparison) => comparison_reads(comparison),
+        Statement::Printn(Printable::Expression(expression)) => expression_reads(expression),
+        Statement::Printn(Printable::Comparison(comparison)) => comparison_reads(comparison),
+        Statement::If {
+            comparison,
+            else_ifs,
+            ..
+        } => {
+            let mut reads = comparison_reads(comparison);
+            for (comparison, _) in else_ifs {
+                reads.extend(comparison_reads(comparison));
+            }
+            reads
+        }
+        Statement::While { comparison, .. } => comparison_reads(comparison),
+        Statement::For { start, end, .. } => {
+            let mut reads = expression_reads(start);
+            reads.extend(expression_reads(end));
+            reads
+        }
+        Statement::Let { expression, .. } => expression_reads(expression),
+        Statement::LetComparison { comparison, .. } => comparison_reads(comparison),
+        Statement::MultiLet(assignments) => assignments
+            .iter()
+            .flat_map(|(_, expression)| expression_reads(expression))
+            .collect(),
+        Statement::IndexAssign {
+            index, expression, ..
+        } => {
+            let mut reads = expression_reads(index);
+            reads.extend(expression_reads(expression));
+            reads
+        }
+        Statement::CompoundAssign {
+            ident, expression, ..
+        } => {
+            let mut reads = vec![ident.as_str()];
+            reads.extend(expression_reads(expression));
+            reads
+        }
+        Statement::Call { arguments, .. } => arguments.iter().flat_map(expression_reads).collect(),
+        Statement::Dump(ident) => vec![ident.as_str()],
+        Statement::Exit(Some(code)) => expression_reads(code),
+        Statement::Spanned(_, statement) => return reads(statement),
+        _ => vec![],
+    };
+    for body in bodies(statement) {
+        found.extend(body.iter().flat_map(reads));
+    }
+    found
+}
+
+// The variables a statement always assigns, as opposed to ones it only might
+// (from inside an IF, say).
+fn sets(statement: &Statement) -> Vec<&str> {
+    match statement.unspanned() {
+        Statement::Let { ident, .. }
+        | Statement::LetComparison { ident, .. }
+        | Statement::Input(ident)
+        | Statement::InputString(ident)
+        | Statement::For { ident, .. } => vec![ident.as_str()],
+        Statement::MultiLet(assignments) => assignments
+            .iter()
+            .map(|(ident, _)| ident.as_str())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// Every variable a statement might assign, including in any blocks inside it.
+fn assigns(statement: &Statement) -> Vec<&str> {
+    let mut found = sets(statement);
+    for body in bodies(statement) {
+        found.extend(body.iter().flat_map(assigns));
+    }
+    found
+}
+
+// Every label a statement can GOTO, including from blocks inside it.
+fn gotos(statement: &Statement) -> Vec<&str> {
+    match statement.unspanned() {
+        Statement::Goto(label) => vec![label.as_str()],
+        statement => bodies(statement)
+            .into_iter()
+            .flat_map(|body| body.iter().flat_map(gotos))
+            .collect(),
+    }
+}
+
+// Whether `ident` can be read by `statements` before they set it.
+fn read_before_set(statements: &[Statement], ident: &str) -> bool {
+    for statement in statements {
+        // A FOR sets its variable only after working out where to start and end
+        let read = match statement.unspanned() {
+            Statement::For { start, end, .. } => {
+                let mut reads = expression_reads(start);
+                reads.extend(expression_reads(end));
+                reads
+            }
+            statement => reads(statement),
+        };
+        if read.contains(&ident) {
+            return true;
+        }
+        if sets(statement).contains(&ident) {
+            return false;
+        }
+    }
+    false
+}
+
+// Warn about each GOTO that jumps forward over the only assignment to a
+// variable that is read after the label it jumps to, since the variable can
+// then be read without ever having been set. Only a GOTO and LABEL in the
+// same list of statements are checked; the GOTO itself can be inside a block.
+pub fn skipped_assignments(statements: &[Statement]) -> Vec<String> {
+    let mut warnings = vec![];
+    for (index, statement) in statements.iter().enumerate() {
+        for label in gotos(statement) {
+            let Some(target) = statements[index + 1..]
+                .iter()
+                .position(|statement| {
+                    matches!(statement.unspanned(), Statement::Label(name) if name == label)
+                })
+                .map(|offset| index + 1 + offset)
+            else {
+                continue;
+            };
+            let before: Vec<&str> = statements[..=index].iter().flat_map(assigns).collect();
+            for ident in statements[index + 1..target].iter().flat_map(assigns) {
+                let warning = format!(
+                    "GOTO {} jumps over the assignment to '{}', which can then be read before it is set",
+                    label, ident
+                );
+                if !before.contains(&ident)
+                    && !warnings.contains(&warning)
+                    && read_before_set(&statements[target..], ident)
+                {
+                    warnings.push(warning);
+                }
+            }
+        }
+    }
+    for statement in statements {
+        for body in bodies(statement) {
+            warnings.extend(skipped_assignments(body));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{lex, TokenIterator};
+
+    fn warnings(input: &str) -> Vec<String> {
+        let tokens = lex(input).unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        skipped_assignments(&statements)
+    }
+
+    #[test]
+    fn test_skipped_assignments() {
+        assert_eq!(
+            warnings("goto skip\nlet x = 1\nlabel skip\nprint x"),
+            ["GOTO skip jumps over the assignment to 'x', which can then be read before it is set"]
+        );
+        // From inside a block, and into a nested list of statements
+        assert_eq!(
+            warnings("let y = 0\nwhile y < 3 repeat\nif y == 1 then\ngoto next\nendif\ninput x\nlabel next\nlet y = y + x\nendwhile")
+                .len(),
+            1
+        );
+
+        for input in [
+            // Set before the jump
+            "let x = 0\ngoto skip\nlet x = 1\nlabel skip\nprint x",
+            // Never read after the label
+            "goto skip\nlet x = 1\nlabel skip\nprint 2",
+            // Set again before it is read
+            "goto skip\nlet x = 1\nlabel skip\nlet x = 2\nprint x",
+            // Jumping backwards doesn't skip anything
+            "label top\nlet x = 1\nprint x\ngoto top",
+        ] {
+            assert!(warnings(input).is_empty(), "{}", input);
+        }
+    }
+}
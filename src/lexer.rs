@@ -1,7 +1,13 @@
 #![allow(dead_code)]
 
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::Arc;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     EOF,
@@ -9,46 +15,340 @@ pub enum Token {
     Number { value: i32 },
     Identifier { name: String },
     String { value: String },
+    // A `#include` line, with the header's <> or quotes kept
+    Include { header: String },
     // Keywords
-    Label { name: String },
+    Label,
     Goto,
     Print,
+    Printn,
     Input,
+    // INPUT$, which reads a line of text rather than a number
+    InputString,
     Let,
     If,
     Then,
+    Else,
+    ElseIf,
     Endif,
     While,
     Repeat,
     Endwhile,
+    Break,
+    Continue,
+    For,
+    To,
+    Endfor,
+    And,
+    Or,
+    Not,
+    Array,
+    Call,
+    Dump,
+    Const,
+    Exit,
     // Operators
     Equal,
     Plus,
     Minus,
     Asterisk,
     Slash,
+    Percent,
+    Caret,
+    Ampersand,
+    Pipe,
+    Tilde,
     EqualEqual,
     NotEqual,
     LessThan,
     GreaterThan,
     LessThanEqual,
     GreaterThanEqual,
+    PlusEqual,
+    MinusEqual,
+    AsteriskEqual,
+    SlashEqual,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
 }
 
-pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
-    let mut tokens = vec![];
-    let mut lines = input.lines().peekable();
+impl Token {
+    // Whether this is one of the words the lexer sets aside, which can't be
+    // used as a name
+    pub fn is_keyword(&self) -> bool {
+        matches!(
+            self,
+            Token::Label
+                | Token::Goto
+                | Token::Print
+                | Token::Printn
+                | Token::Input
+                | Token::InputString
+                | Token::Let
+                | Token::If
+                | Token::Then
+                | Token::Else
+                | Token::ElseIf
+                | Token::Endif
+                | Token::While
+                | Token::Repeat
+                | Token::Endwhile
+                | Token::Break
+                | Token::Continue
+                | Token::For
+                | Token::To
+                | Token::Endfor
+                | Token::And
+                | Token::Or
+                | Token::Not
+                | Token::Array
+                | Token::Call
+                | Token::Dump
+                | Token::Const
+                | Token::Exit
+        )
+    }
+}
 
-    while let Some(line) = lines.next() {
-        let mut chars = line.chars().peekable();
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::EOF => "end of input",
+            Token::Newline => "newline",
+            Token::Number { value } => return write!(f, "number {}", value),
+            Token::Identifier { name } => return write!(f, "identifier '{}'", name),
+            Token::String { value } => return write!(f, "string {:?}", value),
+            Token::Include { header } => return write!(f, "#include {}", header),
+            Token::Label => "LABEL",
+            Token::Goto => "GOTO",
+            Token::Print => "PRINT",
+            Token::Printn => "PRINTN",
+            Token::Input => "INPUT",
+            Token::InputString => "INPUT$",
+            Token::Let => "LET",
+            Token::If => "IF",
+            Token::Then => "THEN",
+            Token::Else => "ELSE",
+            Token::ElseIf => "ELSEIF",
+            Token::Endif => "ENDIF",
+            Token::While => "WHILE",
+            Token::Repeat => "REPEAT",
+            Token::Endwhile => "ENDWHILE",
+            Token::Break => "BREAK",
+            Token::Continue => "CONTINUE",
+            Token::For => "FOR",
+            Token::To => "TO",
+            Token::Endfor => "ENDFOR",
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Not => "NOT",
+            Token::Array => "ARRAY",
+            Token::Call => "CALL",
+            Token::Dump => "DUMP",
+            Token::Const => "CONST",
+            Token::Exit => "EXIT",
+            Token::Equal => "'='",
+            Token::Plus => "'+'",
+            Token::Minus => "'-'",
+            Token::Asterisk => "'*'",
+            Token::Slash => "'/'",
+            Token::Percent => "'%'",
+            Token::Caret => "'^'",
+            Token::Ampersand => "'&'",
+            Token::Pipe => "'|'",
+            Token::Tilde => "'~'",
+            Token::EqualEqual => "'=='",
+            Token::NotEqual => "'!='",
+            Token::LessThan => "'<'",
+            Token::GreaterThan => "'>'",
+            Token::LessThanEqual => "'<='",
+            Token::GreaterThanEqual => "'>='",
+            Token::PlusEqual => "'+='",
+            Token::MinusEqual => "'-='",
+            Token::AsteriskEqual => "'*='",
+            Token::SlashEqual => "'/='",
+            Token::LParen => "'('",
+            Token::RParen => "')'",
+            Token::LBracket => "'['",
+            Token::RBracket => "']'",
+            Token::Comma => "','",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    InvalidEscape(String),
+    UnterminatedString,
+    UnterminatedComment,
+    IntegerTooLarge(String),
+    InvalidNumber(String),
+    InvalidDirective(String),
+    InvalidInclude(String),
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            LexErrorKind::InvalidEscape(sequence) => {
+                write!(f, "invalid escape sequence '{}'", sequence)
+            }
+            LexErrorKind::UnterminatedString => write!(f, "unterminated string literal"),
+            LexErrorKind::UnterminatedComment => write!(f, "unterminated block comment"),
+            LexErrorKind::IntegerTooLarge(literal) => {
+                write!(f, "integer literal too large: {}", literal)
+            }
+            LexErrorKind::InvalidNumber(literal) => {
+                write!(f, "invalid number literal '{}'", literal)
+            }
+            LexErrorKind::InvalidDirective(directive) => {
+                write!(f, "unknown directive '{}'", directive)
+            }
+            LexErrorKind::InvalidInclude(header) => write!(
+                f,
+                "expected <file> or \"file\" after #include, found '{}'",
+                header
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, col {}: {}", self.line, self.col, self.kind)
+    }
+}
+
+impl Error for LexError {}
+
+// Walks the source one char at a time, keeping track of the 1-based line and
+// column of the next char to be read.
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    offset: usize,
+    line: usize,
+    col: usize,
+}
 
-        while let Some(c) = chars.next() {
-            match c {
-                '\0' => tokens.push(Token::EOF),
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Cursor {
+            chars: input.chars().peekable(),
+            offset: 0,
+            line: 1,
+            col: 1,
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    fn next_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+        match self.chars.peek() {
+            Some(c) if func(c) => self.next(),
+            _ => None,
+        }
+    }
+}
+
+// Where a token came from: the byte range it covers in the source, and the
+// line and column it starts on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+// Turns source text into tokens on demand, finishing with an EOF token. Once
+// it has hit an error it produces nothing more.
+pub struct Lexer<'a> {
+    chars: Cursor<'a>,
+    done: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Lexer {
+            chars: Cursor::new(input),
+            done: false,
+        }
+    }
+
+    // The next token and its span, or None once the EOF token has been produced.
+    fn next_spanned(&mut self) -> Result<Option<(Token, Span)>, LexError> {
+        if self.done {
+            return Ok(None);
+        }
+        let chars = &mut self.chars;
+        loop {
+            let (start, line, col) = (chars.offset, chars.line, chars.col);
+            let error = |kind| LexError { kind, line, col };
+            let Some(c) = chars.next() else {
+                self.done = true;
+                let span = Span {
+                    start,
+                    end: start,
+                    line,
+                    col,
+                };
+                return Ok(Some((Token::EOF, span)));
+            };
+            let token = match c {
                 ' ' => continue,
                 '\t' => continue,
                 '\r' => continue,
-                '\n' => tokens.push(Token::Newline),
+                '\n' => Token::Newline,
+                '0' if matches!(chars.peek(), Some('x' | 'X' | 'b' | 'B')) => {
+                    let prefix = chars.next().unwrap();
+                    let radix = if prefix.eq_ignore_ascii_case(&'x') {
+                        16
+                    } else {
+                        2
+                    };
+                    let mut literal = format!("0{}", prefix);
+
+                    // Take any trailing letters too, so `0xZ` is one bad literal
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric()) {
+                        literal.push(c);
+                    }
+
+                    let digits = &literal[2..];
+                    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+                        return Err(error(LexErrorKind::InvalidNumber(literal)));
+                    }
+                    match i32::from_str_radix(digits, radix) {
+                        Ok(value) => Token::Number { value },
+                        Err(_) => return Err(error(LexErrorKind::IntegerTooLarge(literal))),
+                    }
+                }
                 '0'..='9' => {
                     let mut value = c.to_string();
 
@@ -56,21 +356,42 @@ pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                         value.push(chars.next().unwrap());
                     }
 
-                    tokens.push(Token::Number {
-                        value: value.parse().unwrap(),
-                    });
+                    match value.parse() {
+                        Ok(value) => Token::Number { value },
+                        Err(_) => return Err(error(LexErrorKind::IntegerTooLarge(value))),
+                    }
                 }
                 '"' => {
                     let mut value = String::new();
 
-                    while let Some(c) = chars.next() {
-                        if c == '"' {
-                            break;
+                    loop {
+                        let (line, col) = (chars.line, chars.col);
+                        match chars.next_if(|&c| c != '\n') {
+                            Some('"') => break,
+                            None => return Err(error(LexErrorKind::UnterminatedString)),
+                            Some('\\') => {
+                                let escaped = match chars.next_if(|&c| c != '\n') {
+                                    Some('n') => '\n',
+                                    Some('t') => '\t',
+                                    Some('\\') => '\\',
+                                    Some('"') => '"',
+                                    other => {
+                                        let mut sequence = "\\".to_string();
+                                        sequence.extend(other);
+                                        return Err(LexError {
+                                            kind: LexErrorKind::InvalidEscape(sequence),
+                                            line,
+                                            col,
+                                        });
+                                    }
+                                };
+                                value.push(escaped);
+                            }
+                            Some(c) => value.push(c),
                         }
-                        value.push(c);
                     }
 
-                    tokens.push(Token::String { value });
+                    Token::String { value }
                 }
                 'a'..='z' | 'A'..='Z' | '_' => {
                     let mut name = c.to_string();
@@ -82,61 +403,268 @@ pub fn lex(input: &str) -> Result<Vec<Token>, Box<dyn Error>> {
                     }
 
                     match name.to_lowercase().as_str() {
-                        "label" => tokens.push(Token::Label { name }),
-                        "goto" => tokens.push(Token::Goto),
-                        "print" => tokens.push(Token::Print),
-                        "input" => tokens.push(Token::Input),
-                        "let" => tokens.push(Token::Let),
-                        "if" => tokens.push(Token::If),
-                        "then" => tokens.push(Token::Then),
-                        "endif" => tokens.push(Token::Endif),
-                        "while" => tokens.push(Token::While),
-                        "repeat" => tokens.push(Token::Repeat),
-                        "endwhile" => tokens.push(Token::Endwhile),
-                        _ => tokens.push(Token::Identifier { name }),
+                        "label" => Token::Label,
+                        "goto" => Token::Goto,
+                        "print" => Token::Print,
+                        "printn" => Token::Printn,
+                        "input" if chars.next_if(|&c| c == '$').is_some() => Token::InputString,
+                        "input" => Token::Input,
+                        "let" => Token::Let,
+                        "if" => Token::If,
+                        "then" => Token::Then,
+                        "else" => Token::Else,
+                        "elseif" => Token::ElseIf,
+                        "endif" => Token::Endif,
+                        "while" => Token::While,
+                        "repeat" => Token::Repeat,
+                        "endwhile" => Token::Endwhile,
+                        "break" => Token::Break,
+                        "continue" => Token::Continue,
+                        "for" => Token::For,
+                        "to" => Token::To,
+                        "endfor" => Token::Endfor,
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "not" => Token::Not,
+                        "array" => Token::Array,
+                        "call" => Token::Call,
+                        "dump" => Token::Dump,
+                        "const" => Token::Const,
+                        "exit" => Token::Exit,
+                        _ => Token::Identifier { name },
                     }
                 }
                 '=' => {
                     if let Some('=') = chars.peek() {
                         chars.next();
-                        tokens.push(Token::EqualEqual);
+                        Token::EqualEqual
+                    } else {
+                        Token::Equal
+                    }
+                }
+                '+' if chars.next_if(|&c| c == '=').is_some() => Token::PlusEqual,
+                '+' => Token::Plus,
+                '-' if chars.next_if(|&c| c == '=').is_some() => Token::MinusEqual,
+                '-' => Token::Minus,
+                '*' if chars.next_if(|&c| c == '=').is_some() => Token::AsteriskEqual,
+                '*' => Token::Asterisk,
+                '%' => Token::Percent,
+                '^' => Token::Caret,
+                '&' => Token::Ampersand,
+                '|' => Token::Pipe,
+                '~' => Token::Tilde,
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                '[' => Token::LBracket,
+                ']' => Token::RBracket,
+                ',' => Token::Comma,
+                // The rest of an #include line is the header, passed on to the C as is
+                '#' => {
+                    let mut directive = "#".to_string();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+                        directive.push(c);
+                    }
+                    if directive != "#include" {
+                        return Err(error(LexErrorKind::InvalidDirective(directive)));
+                    }
+                    let mut header = String::new();
+                    while let Some(c) = chars.next_if(|&c| c != '\n') {
+                        header.push(c);
+                    }
+                    let header = header.trim().to_string();
+                    let quoted = |open, close| {
+                        header.len() > 2 && header.starts_with(open) && header.ends_with(close)
+                    };
+                    if !quoted('<', '>') && !quoted('"', '"') {
+                        return Err(error(LexErrorKind::InvalidInclude(header)));
+                    }
+                    Token::Include { header }
+                }
+                '/' => {
+                    if let Some('/') = chars.peek() {
+                        // Line comment, skip to (but not past) the end of the line
+                        while chars.next_if(|&c| c != '\n').is_some() {}
+                        continue;
+                    } else if chars.next_if(|&c| c == '*').is_some() {
+                        // Block comment, which can span lines. Like whitespace it
+                        // separates tokens, and any newlines inside don't count.
+                        loop {
+                            match chars.next() {
+                                Some('*') if chars.next_if(|&c| c == '/').is_some() => break,
+                                Some(_) => {}
+                                None => return Err(error(LexErrorKind::UnterminatedComment)),
+                            }
+                        }
+                        continue;
+                    } else if chars.next_if(|&c| c == '=').is_some() {
+                        Token::SlashEqual
                     } else {
-                        tokens.push(Token::Equal);
+                        Token::Slash
                     }
                 }
-                '+' => tokens.push(Token::Plus),
-                '-' => tokens.push(Token::Minus),
-                '*' => tokens.push(Token::Asterisk),
-                '/' => tokens.push(Token::Slash),
                 '!' => {
                     if let Some('=') = chars.peek() {
                         chars.next();
-                        tokens.push(Token::NotEqual);
+                        Token::NotEqual
                     } else {
-                        return Err("Unexpected character '!'".into());
+                        return Err(error(LexErrorKind::UnexpectedCharacter('!')));
                     }
                 }
                 '<' => {
                     if let Some('=') = chars.peek() {
                         chars.next();
-                        tokens.push(Token::LessThanEqual);
+                        Token::LessThanEqual
                     } else {
-                        tokens.push(Token::LessThan);
+                        Token::LessThan
                     }
                 }
                 '>' => {
                     if let Some('=') = chars.peek() {
                         chars.next();
-                        tokens.push(Token::GreaterThanEqual);
+                        Token::GreaterThanEqual
                     } else {
-                        tokens.push(Token::GreaterThan);
+                        Token::GreaterThan
                     }
                 }
-                _ => return Err(format!("Unexpected character '{}'", c).into()),
+                _ => return Err(error(LexErrorKind::UnexpectedCharacter(c))),
+            };
+            let span = Span {
+                start,
+                end: chars.offset,
+                line,
+                col,
+            };
+            return Ok(Some((token, span)));
+        }
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_spanned() {
+            Ok(token) => token.map(|(token, _)| Ok(token)),
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
             }
         }
     }
-    Ok(tokens)
+}
+
+pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
+    Lexer::new(input).collect()
+}
+
+// Like `lex`, along with the span of every token, index for index.
+pub fn lex_with_spans(input: &str) -> Result<(Vec<Token>, Vec<Span>), LexError> {
+    let mut lexer = Lexer::new(input);
+    let mut tokens = vec![];
+    let mut spans = vec![];
+    while let Some((token, span)) = lexer.next_spanned()? {
+        tokens.push(token);
+        spans.push(span);
+    }
+    Ok((tokens, spans))
+}
+
+// A token along with the text it was lexed from and the whitespace and
+// comments just before it, for tools that need to give back the source
+// exactly as it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriviaToken {
+    pub token: Token,
+    pub leading: String,
+    pub text: String,
+}
+
+// Like `lex`, but keeping everything `lex` throws away. Anything after the
+// last token ends up as the EOF token's `leading`.
+pub fn lex_with_trivia(input: &str) -> Result<Vec<TriviaToken>, LexError> {
+    let (tokens, spans) = lex_with_spans(input)?;
+    let mut previous_end = 0;
+    Ok(tokens
+        .into_iter()
+        .zip(spans)
+        .map(|(token, span)| {
+            let leading = input[previous_end..span.start].to_string();
+            previous_end = span.end;
+            TriviaToken {
+                token,
+                leading,
+                text: input[span.start..span.end].to_string(),
+            }
+        })
+        .collect())
+}
+
+// How many sources a `LexCache::new` holds on to.
+const LEX_CACHE_CAPACITY: usize = 32;
+
+// Remembers the tokens of the sources it has lexed, so tools that compile the
+// same files over and over only lex each version of a file once. Sources are
+// looked up by their whole text. Only the most recently lexed `capacity`
+// sources are kept, dropping the oldest first, so a long watch session doesn't
+// keep every saved version of a file. A capacity of 0 turns caching off.
+// Sources that fail to lex aren't cached.
+#[derive(Debug)]
+pub struct LexCache {
+    tokens: HashMap<Arc<str>, Vec<Token>>,
+    // The cached sources, oldest first
+    order: VecDeque<Arc<str>>,
+    capacity: usize,
+    hits: usize,
+    // The tokens of the last source when the capacity is 0, which are only
+    // kept until the next call
+    uncached: Vec<Token>,
+}
+
+impl Default for LexCache {
+    fn default() -> Self {
+        Self::with_capacity(LEX_CACHE_CAPACITY)
+    }
+}
+
+impl LexCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        LexCache {
+            tokens: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+            hits: 0,
+            uncached: vec![],
+        }
+    }
+
+    pub fn get_or_lex(&mut self, input: &str) -> Result<&[Token], LexError> {
+        if self.tokens.contains_key(input) {
+            self.hits += 1;
+            return Ok(&self.tokens[input]);
+        }
+        let tokens = lex(input)?;
+        if self.capacity == 0 {
+            self.uncached = tokens;
+            return Ok(&self.uncached);
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.tokens.remove(&oldest);
+            }
+        }
+        let input: Arc<str> = Arc::from(input);
+        self.order.push_back(Arc::clone(&input));
+        Ok(self.tokens.entry(input).or_insert(tokens))
+    }
+
+    // How many calls to `get_or_lex` found their tokens already cached
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -146,9 +674,20 @@ pub struct TokenIterator<'a> {
 }
 
 impl<'a> TokenIterator<'a> {
-    pub fn new(tokens: &'a Vec<Token>) -> Self {
+    pub fn new(tokens: &'a [Token]) -> Self {
         TokenIterator { tokens, index: 0 }
     }
+
+    // The next token, without consuming it.
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.peek_nth(0)
+    }
+
+    // The token `n` places after the next one, without consuming anything, so
+    // `peek_nth(1)` looks two tokens ahead.
+    pub fn peek_nth(&self, n: usize) -> Option<&'a Token> {
+        self.tokens.get(self.index + n)
+    }
 }
 
 impl Iterator for TokenIterator<'_> {
@@ -163,8 +702,15 @@ impl Iterator for TokenIterator<'_> {
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.tokens.len() - self.index;
+        (remaining, Some(remaining))
+    }
 }
 
+impl ExactSizeIterator for TokenIterator<'_> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -198,6 +744,431 @@ goto main"#;
         for token in &tokens {
             println!("Token: {:?}", token);
         }
-        assert_eq!(tokens.len(), 28);
+        assert_eq!(tokens.len(), 40);
+    }
+
+    #[test]
+    fn test_lex_label() {
+        let tokens = lex("label foo").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Label,
+                Token::Identifier {
+                    name: "foo".to_string()
+                },
+                Token::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_newlines() {
+        let tokens = lex("print 1\n\nprint 2\n").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Print,
+                Token::Number { value: 1 },
+                Token::Newline,
+                Token::Newline,
+                Token::Print,
+                Token::Number { value: 2 },
+                Token::Newline,
+                Token::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_eof() {
+        for input in ["", "print 1", "print 1\n", "let x = 2\nlabel foo\n\n"] {
+            let tokens = lex(input).unwrap();
+            assert_eq!(tokens.last(), Some(&Token::EOF));
+            assert_eq!(tokens.iter().filter(|t| **t == Token::EOF).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_lex_error_position() {
+        let error = lex("let x = 1\nprint x\nlet y @ 2").unwrap_err();
+        assert_eq!(
+            error,
+            LexError {
+                kind: LexErrorKind::UnexpectedCharacter('@'),
+                line: 3,
+                col: 7,
+            }
+        );
+        assert_eq!(error.to_string(), "line 3, col 7: unexpected character '@'");
+    }
+
+    #[test]
+    fn test_lex_line_comments() {
+        let tokens = lex("let x = 1 // set x\nprint x").unwrap();
+        assert_eq!(tokens, lex("let x = 1\nprint x").unwrap(),);
+
+        let tokens = lex("// just a comment\nprint \"a // b\" // trailing").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Newline,
+                Token::Print,
+                Token::String {
+                    value: "a // b".to_string()
+                },
+                Token::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lex_block_comments() {
+        let tokens = lex("let x = /* one */ 1\nprint x/**/").unwrap();
+        assert_eq!(tokens, lex("let x = 1\nprint x").unwrap());
+
+        let (tokens, spans) = lex_with_spans("/* spans\n * lines **/\nprint 1/2").unwrap();
+        assert_eq!(
+            tokens,
+            [
+                Token::Newline,
+                Token::Print,
+                Token::Number { value: 1 },
+                Token::Slash,
+                Token::Number { value: 2 },
+                Token::EOF,
+            ]
+        );
+        assert_eq!((spans[1].line, spans[1].col), (3, 1));
+
+        assert_eq!(
+            lex("print 1\n  /* oops\nprint 2 *").unwrap_err(),
+            LexError {
+                kind: LexErrorKind::UnterminatedComment,
+                line: 2,
+                col: 3,
+            }
+        );
+        // Comments don't nest
+        assert_eq!(
+            lex("/* /* */ */").unwrap(),
+            [Token::Asterisk, Token::Slash, Token::EOF]
+        );
+    }
+
+    #[test]
+    fn test_lex_string_escapes() {
+        let tokens = lex(r#"print "a\"b" "tab\tnew\nslash\\""#).unwrap();
+        assert_eq!(
+            tokens[1..3],
+            [
+                Token::String {
+                    value: "a\"b".to_string()
+                },
+                Token::String {
+                    value: "tab\tnew\nslash\\".to_string()
+                },
+            ]
+        );
+
+        let error = lex(r#"print "\q""#).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::InvalidEscape("\\q".to_string()));
+        assert_eq!((error.line, error.col), (1, 8));
+    }
+
+    #[test]
+    fn test_lex_unterminated_string() {
+        let error = lex("let x = 1\nprint \"oops\nprint x").unwrap_err();
+        assert_eq!(
+            error,
+            LexError {
+                kind: LexErrorKind::UnterminatedString,
+                line: 2,
+                col: 7,
+            }
+        );
+        assert!(lex("print \"oops").is_err());
+    }
+
+    #[test]
+    fn test_lex_integer_too_large() {
+        assert_eq!(
+            lex("print 2147483647").unwrap()[1],
+            Token::Number { value: i32::MAX }
+        );
+
+        let error = lex("print 99999999999").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1, col 7: integer literal too large: 99999999999"
+        );
+    }
+
+    #[test]
+    fn test_lex_hex_and_binary() {
+        assert_eq!(lex("0xFF").unwrap()[0], Token::Number { value: 255 });
+        assert_eq!(lex("0Xff").unwrap()[0], Token::Number { value: 255 });
+        assert_eq!(lex("0b1010").unwrap()[0], Token::Number { value: 10 });
+        assert_eq!(
+            lex("0 0x7FFFFFFF").unwrap()[1],
+            Token::Number { value: i32::MAX }
+        );
+
+        for (input, message) in [
+            ("let x = 0xZ", "line 1, col 9: invalid number literal '0xZ'"),
+            (
+                "let x = 0b102",
+                "line 1, col 9: invalid number literal '0b102'",
+            ),
+            ("let x = 0x", "line 1, col 9: invalid number literal '0x'"),
+            (
+                "let x = 0x100000000",
+                "line 1, col 9: integer literal too large: 0x100000000",
+            ),
+        ] {
+            assert_eq!(lex(input).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_lex_elseif() {
+        assert_eq!(lex("elseif").unwrap()[0], Token::ElseIf);
+        assert_eq!(lex("ELSEIF").unwrap()[0], Token::ElseIf);
+        assert_eq!(lex("else if").unwrap()[..2], [Token::Else, Token::If]);
+        assert_eq!(
+            lex("elseiffy").unwrap()[0],
+            Token::Identifier {
+                name: "elseiffy".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_lex_input_string() {
+        let name = Token::Identifier {
+            name: "name".to_string(),
+        };
+        assert_eq!(
+            lex("input$ name").unwrap()[..2],
+            [Token::InputString, name.clone()]
+        );
+        assert_eq!(lex("INPUT$name").unwrap()[..2], [Token::InputString, name]);
+        assert!(lex("input $ name").is_err());
+        assert!(lex("inputs$").is_err());
+    }
+
+    #[test]
+    fn test_token_display() {
+        let display = |token: Token| token.to_string();
+        assert_eq!(display(Token::Plus), "'+'");
+        assert_eq!(display(Token::EqualEqual), "'=='");
+        assert_eq!(display(Token::Print), "PRINT");
+        assert_eq!(
+            display(Token::Identifier {
+                name: "x".to_string()
+            }),
+            "identifier 'x'"
+        );
+        assert_eq!(display(Token::Number { value: 5 }), "number 5");
+        assert_eq!(
+            display(Token::String {
+                value: "hi\n".to_string()
+            }),
+            "string \"hi\\n\""
+        );
+        assert_eq!(display(Token::EOF), "end of input");
+    }
+
+    #[test]
+    fn test_lex_with_spans() {
+        let input = "let x = \"é\" // note\nprint x";
+        let (tokens, spans) = lex_with_spans(input).unwrap();
+        assert_eq!(tokens.len(), spans.len());
+        let text: Vec<&str> = spans
+            .iter()
+            .map(|span| &input[span.start..span.end])
+            .collect();
+        assert_eq!(text, ["let", "x", "=", "\"é\"", "\n", "print", "x", ""]);
+        assert_eq!((spans[5].line, spans[5].col), (2, 1));
+        assert_eq!((spans[6].line, spans[6].col), (2, 7));
+    }
+
+    #[test]
+    fn test_lex_cache() {
+        let mut cache = LexCache::new();
+        assert_eq!(
+            cache.get_or_lex("print 1").unwrap(),
+            lex("print 1").unwrap()
+        );
+        assert_eq!(cache.hits(), 0);
+        assert_eq!(
+            cache.get_or_lex("print 1").unwrap(),
+            lex("print 1").unwrap()
+        );
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(
+            cache.get_or_lex("print 2").unwrap(),
+            lex("print 2").unwrap()
+        );
+        assert_eq!(cache.hits(), 1);
+
+        assert!(cache.get_or_lex("print \"open").is_err());
+        assert!(cache.get_or_lex("print \"open").is_err());
+        assert_eq!(cache.hits(), 1);
+
+        // The oldest source makes way once the cache is full
+        let mut cache = LexCache::with_capacity(2);
+        for input in ["print 1", "print 2", "print 3", "print 2"] {
+            assert_eq!(cache.get_or_lex(input).unwrap(), lex(input).unwrap());
+        }
+        assert_eq!(cache.hits(), 1);
+        cache.get_or_lex("print 1").unwrap();
+        assert_eq!(cache.hits(), 1);
+
+        // Nothing is kept with a capacity of 0
+        let mut cache = LexCache::with_capacity(0);
+        for input in ["print 1", "print 1", "print 2"] {
+            assert_eq!(cache.get_or_lex(input).unwrap(), lex(input).unwrap());
+        }
+        assert_eq!(cache.hits(), 0);
+
+        // So `watch` could hand its cache to another thread
+        fn assert_send<T: Send>() {}
+        assert_send::<LexCache>();
+    }
+
+    #[test]
+    fn test_lex_with_trivia() {
+        let input = "// counts\r\nlet  x =\t1 /* one\nor two */ + 2\n\n  print \"é\"  // done\n";
+        let tokens = lex_with_trivia(input).unwrap();
+        let source: String = tokens
+            .iter()
+            .map(|token| format!("{}{}", token.leading, token.text))
+            .collect();
+        assert_eq!(source, input);
+
+        assert_eq!(tokens[0].leading, "// counts\r");
+        assert_eq!(tokens[0].token, Token::Newline);
+        assert_eq!(tokens[4].leading, "\t");
+        assert_eq!(tokens[4].text, "1");
+        assert_eq!(tokens[5].leading, " /* one\nor two */ ");
+        assert_eq!(tokens.last().unwrap().leading, "");
+        assert_eq!(
+            tokens
+                .into_iter()
+                .map(|token| token.token)
+                .collect::<Vec<Token>>(),
+            lex(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_token_iterator_from_slice() {
+        let tokens = [Token::Print, Token::Number { value: 1 }, Token::EOF];
+        let mut iterator = TokenIterator::new(&tokens);
+        assert_eq!(iterator.len(), 3);
+        assert_eq!(iterator.next(), Some(Token::Print));
+        assert_eq!(iterator.len(), 2);
+        assert_eq!(iterator.collect::<Vec<Token>>(), tokens[1..]);
+    }
+
+    #[test]
+    fn test_token_iterator_peek_nth() {
+        let tokens = lex("let x = 1").unwrap();
+        let mut iterator = TokenIterator::new(&tokens);
+        assert_eq!(iterator.peek(), Some(&Token::Let));
+        assert_eq!(
+            iterator.peek_nth(1),
+            Some(&Token::Identifier {
+                name: "x".to_string()
+            })
+        );
+        assert_eq!(iterator.peek_nth(4), Some(&Token::EOF));
+        assert_eq!(iterator.peek_nth(5), None);
+        // Nothing has been consumed
+        assert_eq!(iterator.next(), Some(Token::Let));
+        assert_eq!(iterator.peek_nth(1), Some(&Token::Equal));
+    }
+
+    #[test]
+    fn test_lex_compound_assignment() {
+        assert_eq!(
+            lex("x += 1 -= *= /=").unwrap()[1..6],
+            [
+                Token::PlusEqual,
+                Token::Number { value: 1 },
+                Token::MinusEqual,
+                Token::AsteriskEqual,
+                Token::SlashEqual,
+            ]
+        );
+        // The operators still lex on their own, and `=-` is not `-=`
+        assert_eq!(
+            lex("1 + = -1 =-1").unwrap()[1..7],
+            [
+                Token::Plus,
+                Token::Equal,
+                Token::Minus,
+                Token::Number { value: 1 },
+                Token::Equal,
+                Token::Minus,
+            ]
+        );
+        assert_eq!(
+            lex("x /// =").unwrap(),
+            [
+                Token::Identifier {
+                    name: "x".to_string()
+                },
+                Token::EOF
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lexer_is_lazy() {
+        let input = "let x = 1 /* one */\nprint x + 0x10\n";
+        let tokens: Vec<Token> = Lexer::new(input).map(Result::unwrap).collect();
+        assert_eq!(tokens, lex(input).unwrap());
+
+        // Tokens before an error come out fine, and nothing comes after it
+        let mut lexer = Lexer::new("print 1 @ 2");
+        assert_eq!(lexer.next(), Some(Ok(Token::Print)));
+        assert_eq!(lexer.next(), Some(Ok(Token::Number { value: 1 })));
+        assert!(matches!(lexer.next(), Some(Err(LexError { col: 9, .. }))));
+        assert_eq!(lexer.next(), None);
+
+        let mut lexer = Lexer::new("");
+        assert_eq!(lexer.next(), Some(Ok(Token::EOF)));
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn test_include() {
+        assert_eq!(
+            lex("#include <math.h>\n#include  \"my lib.h\"  \nprint 1").unwrap()[..4],
+            [
+                Token::Include {
+                    header: "<math.h>".to_string()
+                },
+                Token::Newline,
+                Token::Include {
+                    header: "\"my lib.h\"".to_string()
+                },
+                Token::Newline,
+            ]
+        );
+
+        let error = lex("#define X 1").unwrap_err();
+        assert_eq!(
+            error.kind,
+            LexErrorKind::InvalidDirective("#define".to_string())
+        );
+        let error = lex("\n#include math.h").unwrap_err();
+        assert_eq!((error.line, error.col), (2, 1));
+        assert_eq!(
+            error.to_string(),
+            "line 2, col 1: expected <file> or \"file\" after #include, found 'math.h'"
+        );
     }
 }
@@ -0,0 +1,28 @@
+use std::error::Error;
+
+pub mod emitter;
+pub mod flow;
+pub mod lexer;
+pub mod optimizer;
+pub mod parser;
+pub mod printer;
+pub mod types;
+
+// Run the whole pipeline over a teeny program and return the generated C source.
+pub fn compile(source: &str) -> Result<String, Box<dyn Error>> {
+    compile_tokens(&lexer::lex(source)?)
+}
+
+// The rest of the pipeline, for a program that has already been lexed (say,
+// through a `lexer::LexCache`).
+pub fn compile_tokens(tokens: &[lexer::Token]) -> Result<String, Box<dyn Error>> {
+    let mut token_iterator = lexer::TokenIterator::new(tokens);
+    let parser::AST::Program(statements) = parser::parse(&mut token_iterator)?;
+    Ok(emitter::emit_program(statements)?.source)
+}
+
+// The generated C split into lines, which is easier for tests to pick apart
+// than one long string.
+pub fn compile_str(source: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(compile(source)?.lines().map(str::to_string).collect())
+}
@@ -1,48 +1,784 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::{
+    env,
+    error::Error,
     fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process,
     string::String,
+    thread,
+    time::{Duration, SystemTime},
 };
-
-mod lexer;
-mod parser;
-mod emitter;
+use teeny_compiler::{emitter, lexer, optimizer, parser, printer, types::IntType};
 
 #[derive(Parser, Debug)]
 #[command(name = "teeny compiler", version, about = "Simple compiler for a BASIC-like grammar into C", long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Command,
+    /// Trace each step of the parser, and each stage of compile, to stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
 }
 
 #[derive(Subcommand, Debug)]
 #[command(version, about, long_about = None)]
 enum Command {
-    /// Compile a single .tiny file
+    /// Compile one or more .tiny files into a single C program
+    #[command()]
+    Compile {
+        /// Source files, compiled in order as if they were one program
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Write the generated C to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Build the generated C with $CC (or cc) and run the program
+        #[arg(long)]
+        run: bool,
+        /// Mark each statement in the C with the source line it came from
+        #[arg(long)]
+        line_comments: bool,
+        /// Fold constant expressions and drop unreachable code before emitting
+        #[arg(long)]
+        optimize: bool,
+        /// Abort with an error on integer overflow in +, - and *
+        #[arg(long)]
+        checked_arith: bool,
+        /// The C type to use for integers: int, long or int64_t
+        #[arg(long, default_value = "int")]
+        int_type: IntType,
+        /// Which stage of compiling to output
+        #[arg(long, value_enum, default_value_t = EmitStage::C)]
+        emit: EmitStage,
+    },
+    /// Compile every .tiny file in a directory to its own .c file
+    #[command()]
+    CompileDir {
+        dir: String,
+        /// Where to write the .c files, created if it doesn't exist
+        out_dir: String,
+    },
+    /// Recompile a .tiny file every time it changes, until stopped with Ctrl-C
+    #[command()]
+    Watch {
+        path: String,
+        /// Where to write the generated C, by default next to the source as .c
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Print the tokens of a .tiny file, one per line
+    #[command()]
+    Tokens { path: String },
+    /// Print the parsed AST of a .tiny file as an indented tree
     #[command()]
-    Compile { path: String },
+    Ast {
+        path: String,
+        /// Show the line and column each statement starts at
+        #[arg(long)]
+        spans: bool,
+    },
+    /// Check a .tiny file for errors without generating any C
+    #[command()]
+    Check { path: String },
+    /// Rewrite a .tiny file with canonical spacing and indentation, dropping comments
+    #[command()]
+    Fmt {
+        path: String,
+        /// Overwrite the file instead of printing the result to stdout
+        #[arg(long)]
+        write: bool,
+    },
+    /// Read statements from stdin and print the C for each one
+    #[command()]
+    Repl,
+}
+
+// What `compile --emit` outputs: the tokens, the AST or the generated C.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum EmitStage {
+    Tokens,
+    Ast,
+    C,
+}
+
+// Net number of blocks a line opens, so the REPL knows to keep reading until
+// an IF/WHILE/FOR has been closed. An IF with its body on the same line as
+// THEN has no ENDIF, so that THEN cancels out the IF.
+fn block_depth(tokens: &[lexer::Token]) -> i32 {
+    tokens
+        .iter()
+        .zip(tokens.iter().skip(1).map(Some).chain([None]))
+        .map(|(token, next)| match (token, next) {
+            (lexer::Token::If | lexer::Token::While | lexer::Token::For, _) => 1,
+            (lexer::Token::Endif | lexer::Token::Endwhile | lexer::Token::Endfor, _) => -1,
+            (lexer::Token::Then, Some(next))
+                if !matches!(next, lexer::Token::Newline | lexer::Token::EOF) =>
+            {
+                -1
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+fn repl(input: impl BufRead, output: &mut impl Write) -> io::Result<()> {
+    let mut emitter = emitter::Emitter::new();
+    let mut declared = 0;
+    let mut defined = 0;
+    let mut buffer = String::new();
+
+    for line in input.lines() {
+        buffer.push_str(&line?);
+        buffer.push('\n');
+
+        let tokens = match lexer::lex(&buffer) {
+            Ok(tokens) => tokens,
+            Err(error) => {
+                writeln!(output, "error: {}", error)?;
+                buffer.clear();
+                continue;
+            }
+        };
+        if block_depth(&tokens) > 0 {
+            continue;
+        }
+        buffer.clear();
+
+        let mut token_iterator = lexer::TokenIterator::new(&tokens);
+        let result = parser::parse(&mut token_iterator)
+            .map_err(|error| error.into())
+            .and_then(|parse_out| {
+                let parser::AST::Program(statements) = parse_out;
+                emitter.emit_statements(statements)
+            });
+        match result {
+            Ok(lines) => {
+                // Define any constants and declare any variables this input
+                // introduced before using them
+                let defines = emitter.defines();
+                let declarations = emitter.declarations();
+                for line in defines[defined..]
+                    .iter()
+                    .chain(&declarations[declared..])
+                    .chain(&lines)
+                {
+                    writeln!(output, "{}", line)?;
+                }
+                defined = defines.len();
+                declared = declarations.len();
+            }
+            Err(error) => writeln!(output, "error: {}", error)?,
+        }
+    }
+    Ok(())
+}
+
+fn dump_tokens(input: &str, output: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let tokens = lexer::lex(input)?;
+    for (index, token) in tokens.iter().enumerate() {
+        writeln!(output, "{:>4}: {:?}", index, token)?;
+    }
+    Ok(())
+}
+
+fn dump_ast(input: &str, spans: bool, output: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let (tokens, token_spans) = lexer::lex_with_spans(input)?;
+    let mut token_iterator = lexer::TokenIterator::new(&tokens);
+    let ast = if spans {
+        parser::parse_with_spans(&mut token_iterator, &token_spans)?
+    } else {
+        parser::parse(&mut token_iterator)?
+    };
+    write!(output, "{}", printer::print_ast(&ast))?;
+    Ok(())
+}
+
+// Run `dump` over each file in turn, writing everything it outputs to
+// `output_path`, or to stdout if there is none.
+fn dump_files(
+    paths: &[String],
+    output_path: Option<&str>,
+    dump: impl Fn(&str, &mut Vec<u8>) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut output = Vec::new();
+    for path in paths {
+        dump(&read_source(path)?, &mut output)?;
+    }
+    match output_path {
+        Some(output_path) => fs::write(output_path, output)
+            .map_err(|error| format!("could not write {}: {}", output_path, error))?,
+        None => io::stdout().write_all(&output)?,
+    }
+    Ok(())
+}
+
+// Everything `compile` checks, reported with the line it went wrong on.
+// Parse with spans, so a parse error and any error found later on (in the
+// types pass or the emitter) says which line it is on.
+fn parse_spanned(
+    tokens: &[lexer::Token],
+    spans: &[lexer::Span],
+) -> Result<Vec<parser::Statement>, Box<dyn Error>> {
+    let mut token_iterator = lexer::TokenIterator::new(tokens);
+    let ast = parser::parse_with_spans(&mut token_iterator, spans).map_err(|error| {
+        // The last token the parser took before it gave up
+        let index = (tokens.len() - token_iterator.len()).saturating_sub(1);
+        format!("line {}: {}", spans[index].line, error)
+    })?;
+    let parser::AST::Program(statements) = ast;
+    Ok(statements)
+}
+
+fn check(input: &str) -> Result<(), Box<dyn Error>> {
+    let (tokens, spans) = lexer::lex_with_spans(input)?;
+    emitter::emit_program(parse_spanned(&tokens, &spans)?)?;
+    Ok(())
+}
+
+fn format_source(input: &str) -> Result<String, Box<dyn Error>> {
+    let tokens = lexer::lex(input)?;
+    let mut token_iterator = lexer::TokenIterator::new(&tokens);
+    let ast = parser::parse(&mut token_iterator)?;
+    Ok(printer::unparse(&ast))
+}
+
+// The file is only rewritten once it has parsed, so an error leaves it as it was.
+fn format_file(path: &str, write: bool) -> Result<(), Box<dyn Error>> {
+    let formatted = format_source(&read_source(path)?)?;
+    if write {
+        fs::write(path, formatted)
+            .map_err(|error| format!("could not write {}: {}", path, error))?;
+    } else {
+        print!("{}", formatted);
+    }
+    Ok(())
+}
+
+// Write the emitted C to `path`, creating any missing parent directories.
+fn write_output(path: &Path, source: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, source)
+}
+
+fn read_source(path: &str) -> Result<String, Box<dyn Error>> {
+    fs::read_to_string(path).map_err(|error| format!("could not read {}: {}", path, error).into())
+}
+
+// The comment the generated C opens with, saying what it was generated from.
+fn provenance(paths: &[String]) -> String {
+    // A `*/` in a file name would end the comment early
+    let sources = paths.join(", ").replace("*/", "* /");
+    format!(
+        "/* Generated by teeny compiler v{} from {} */",
+        env!("CARGO_PKG_VERSION"),
+        sources
+    )
+}
+
+// Build a C file with the system compiler and run the result, letting the
+// compiler's diagnostics and the program's output through to our terminal.
+fn run_c(c_path: &Path) -> Result<(), Box<dyn Error>> {
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let binary = env::temp_dir().join(format!("teeny-run-{}", process::id()));
+    io::stdout().flush()?;
+    let status = process::Command::new(&cc)
+        .arg(c_path)
+        .arg("-o")
+        .arg(&binary)
+        .arg("-lm")
+        .status()
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => format!(
+                "could not find a C compiler '{}'; install one or set $CC",
+                cc
+            ),
+            _ => format!("could not run {}: {}", cc, error),
+        })?;
+    if !status.success() {
+        return Err(format!("{} failed to compile {}", cc, c_path.display()).into());
+    }
+    let status = process::Command::new(&binary).status();
+    fs::remove_file(&binary)?;
+    let status = status.map_err(|error| format!("could not run program: {}", error))?;
+    if !status.success() {
+        return Err(format!("program exited with {}", status).into());
+    }
+    Ok(())
+}
+
+fn compile(
+    paths: &[String],
+    output_path: Option<&str>,
+    run: bool,
+    optimize: bool,
+    options: emitter::EmitOptions,
+    verbose: bool,
+) -> Result<(), Box<dyn Error>> {
+    // Stdout is kept for the C itself (or the program, with --run)
+    let progress = |message: &str| {
+        if verbose {
+            eprint!("{}", message);
+        }
+    };
+    let mut statements = vec![];
+    // Which file each label came from, so a clash can name both files
+    let mut labels: Vec<(String, &str)> = vec![];
+    for path in paths {
+        let input = read_source(path)?;
+        progress("Lexing... ");
+        let (lex_out, spans) = lexer::lex_with_spans(&input)?;
+        progress("OK!\nParsing... ");
+        let mut parsed = parse_spanned(&lex_out, &spans)?;
+        progress("OK!\n");
+        for label in emitter::defined_labels(&parsed) {
+            if let Some((_, other)) = labels
+                .iter()
+                .find(|(name, other)| name == label && other != path)
+            {
+                return Err(format!(
+                    "label '{}' is defined in both {} and {}",
+                    label, other, path
+                )
+                .into());
+            }
+            labels.push((label.to_string(), path));
+        }
+        statements.append(&mut parsed);
+    }
+    if optimize {
+        statements = optimizer::optimize(statements)?;
+    }
+    progress("Emitting... ");
+    let program = emitter::emit_program_with_options(statements, options)?;
+    let source = format!("{}\n{}", provenance(paths), program.source);
+    progress("OK!\n");
+    for warning in program.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    // Running needs the C on disk, so fall back to a temp file when no -o is given
+    let c_path = match (output_path, run) {
+        (Some(output_path), _) => Some(PathBuf::from(output_path)),
+        (None, true) => Some(env::temp_dir().join(format!("teeny-run-{}.c", process::id()))),
+        (None, false) => None,
+    };
+    match &c_path {
+        Some(c_path) => {
+            write_output(c_path, &source)
+                .map_err(|error| format!("could not write {}: {}", c_path.display(), error))?;
+            if output_path.is_some() {
+                println!("Wrote {}", c_path.display());
+            }
+        }
+        None => print!("{}", source),
+    }
+    if let Some(c_path) = c_path.filter(|_| run) {
+        let result = run_c(&c_path);
+        if output_path.is_none() {
+            fs::remove_file(&c_path)?;
+        }
+        result?;
+    }
+    Ok(())
+}
+
+// Compile one file on its own to `c_path`, without any of the progress output
+// `compile` prints, lexing it through `cache`.
+fn compile_to(path: &Path, c_path: &Path, cache: &mut lexer::LexCache) -> Result<(), String> {
+    let input = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let tokens = cache
+        .get_or_lex(&input)
+        .map_err(|error| error.to_string())?;
+    let output = teeny_compiler::compile_tokens(tokens).map_err(|error| error.to_string())?;
+    let source = format!("{}\n{}", provenance(&[path.display().to_string()]), output);
+    write_output(c_path, &source)
+        .map_err(|error| format!("could not write {}: {}", c_path.display(), error))
+}
+
+// Compile one file on its own, writing the C next to the others in `out_dir`.
+fn compile_file(path: &Path, out_dir: &Path) -> Result<PathBuf, String> {
+    let c_path = out_dir.join(path.with_extension("c").file_name().unwrap_or_default());
+    // Each file is only compiled once, so there is nothing worth caching
+    compile_to(path, &c_path, &mut lexer::LexCache::with_capacity(0))?;
+    Ok(c_path)
+}
+
+// Compile each .tiny file in `dir` on its own thread, since they don't depend
+// on each other. A file that fails doesn't stop the rest; every failure is
+// reported and counted in the summary at the end.
+fn compile_dir(dir: &str, out_dir: &str) -> Result<(), Box<dyn Error>> {
+    let mut paths = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<PathBuf>>>()
+        })
+        .map_err(|error| format!("could not read {}: {}", dir, error))?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|extension| extension == "tiny")
+    });
+    paths.sort();
+
+    let results: Vec<Result<PathBuf, String>> = thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .iter()
+            .map(|path| scope.spawn(|| compile_file(path, Path::new(out_dir))))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err("the compiler panicked".to_string()))
+            })
+            .collect()
+    });
+
+    let mut failed = 0;
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Ok(c_path) => println!("{} -> {}", path.display(), c_path.display()),
+            Err(error) => {
+                failed += 1;
+                eprintln!("error: {}: {}", path.display(), error);
+            }
+        }
+    }
+    println!("Compiled {} of {} files", paths.len() - failed, paths.len());
+    if failed > 0 {
+        return Err(format!("{} of {} files failed to compile", failed, paths.len()).into());
+    }
+    Ok(())
+}
+
+// How often `watch` looks at the file, and how long the file has to stay
+// the same after changing before it is rebuilt, so that an editor writing it
+// in several goes only sets off one build.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+// Notices a file changing by polling its modification time.
+struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    debounce: Duration,
+}
+
+impl Watcher {
+    fn new(path: &Path, debounce: Duration) -> Self {
+        Watcher {
+            path: path.to_path_buf(),
+            modified: modified_time(path),
+            debounce,
+        }
+    }
+
+    // Whether the file has changed since it was last looked at, waiting for
+    // it to settle first. A file that has gone missing (as some editors do
+    // part way through saving) hasn't changed yet.
+    fn changed(&mut self) -> bool {
+        let mut modified = modified_time(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        loop {
+            thread::sleep(self.debounce);
+            let settled = modified_time(&self.path);
+            if settled == modified {
+                break;
+            }
+            modified = settled;
+        }
+        self.modified = modified;
+        modified.is_some()
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Compile `path` now and again every time it changes. Errors are printed
+// rather than ending the watch. Nothing is left half done between builds, so
+// the usual Ctrl-C handling is all it takes to stop cleanly.
+fn watch(path: &str, output_path: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(path);
+    let c_path = output_path
+        .map(PathBuf::from)
+        .unwrap_or_else(|| path.with_extension("c"));
+    let mut watcher = Watcher::new(path, WATCH_DEBOUNCE);
+    // Saving without changing anything, or undoing back to an earlier
+    // version, doesn't need lexing again
+    let mut cache = lexer::LexCache::new();
+    println!("Watching {} (Ctrl-C to stop)", path.display());
+    loop {
+        match compile_to(path, &c_path, &mut cache) {
+            Ok(()) => println!("Wrote {}", c_path.display()),
+            Err(error) => eprintln!("error: {}", error),
+        }
+        while !watcher.changed() {
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
 }
 
 fn main() {
     let args = Cli::parse();
-    let target_dir = "./tinycode/";
-
-    match args.command {
-        Command::Compile { path } => {
-            let input = fs::read_to_string(path).unwrap();
-            println!("{}", input);
-            print!("Lexing... ");
-            let lex_out = lexer::lex(&input).unwrap();
-            print!("OK!\nParsing... ");
-            let mut token_iterator = lexer::TokenIterator::new(&lex_out).peekable();
-            let parse_out = parser::parse(&mut token_iterator).unwrap();
-            let parser::AST::Program(statements) = parse_out;
-            print!("OK!\nEmitting... ");
-            let output = emitter::emit_program(statements).unwrap();
-            print!("OK!\nOutput: ");
-            for line in output {
-                println!("{}", line);
-            }
+    parser::set_verbose(args.verbose);
+
+    let result = match args.command {
+        Command::Compile {
+            run: true,
+            emit: EmitStage::Tokens | EmitStage::Ast,
+            ..
+        } => Err("--run needs --emit c".into()),
+        Command::Compile {
+            paths,
+            output,
+            emit: EmitStage::Tokens,
+            ..
+        } => dump_files(&paths, output.as_deref(), |input, output| {
+            dump_tokens(input, output)
+        }),
+        Command::Compile {
+            paths,
+            output,
+            emit: EmitStage::Ast,
+            ..
+        } => dump_files(&paths, output.as_deref(), |input, output| {
+            dump_ast(input, false, output)
+        }),
+        Command::Compile {
+            paths,
+            output,
+            run,
+            line_comments,
+            optimize,
+            int_type,
+            checked_arith,
+            emit: EmitStage::C,
+        } => compile(
+            &paths,
+            output.as_deref(),
+            run,
+            optimize,
+            emitter::EmitOptions {
+                int_type,
+                checked_arith,
+                line_comments,
+            },
+            args.verbose,
+        ),
+        Command::CompileDir { dir, out_dir } => compile_dir(&dir, &out_dir),
+        Command::Watch { path, output } => watch(&path, output.as_deref()),
+        Command::Tokens { path } => {
+            read_source(&path).and_then(|input| dump_tokens(&input, &mut io::stdout()))
         }
+        Command::Ast { path, spans } => {
+            read_source(&path).and_then(|input| dump_ast(&input, spans, &mut io::stdout()))
+        }
+        Command::Check { path } => read_source(&path).and_then(|input| check(&input)),
+        Command::Fmt { path, write } => format_file(&path, write),
+        Command::Repl => repl(io::stdin().lock(), &mut io::stdout()).map_err(|error| error.into()),
+    };
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repl() {
+        let input = "let x = 1\nprint x\nlet x = 2 +\nif x == 1 then\nprint 2\nendif\nif x == 2 then print 3\nconst n = 4\nprint n\n";
+        let mut output = Vec::new();
+        repl(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "int x;");
+        assert_eq!(lines[1], "x = 1;");
+        assert_eq!(lines[2], "printf(\"%d\\n\", x);");
+        assert!(lines[3].starts_with("error: "));
+        assert_eq!(
+            lines[4..7],
+            ["if (x == 1) {", "    printf(\"%d\\n\", 2);", "}"]
+        );
+        assert_eq!(
+            lines[7..10],
+            ["if (x == 2) {", "    printf(\"%d\\n\", 3);", "}"]
+        );
+        assert_eq!(lines[10..], ["#define n 4", "printf(\"%d\\n\", n);"]);
+    }
+
+    #[test]
+    fn test_write_output() {
+        let input = fs::read_to_string("tinycode/hello.tiny").unwrap();
+        let tokens = lexer::lex(&input).unwrap();
+        let mut token_iterator = lexer::TokenIterator::new(&tokens);
+        let parser::AST::Program(statements) = parser::parse(&mut token_iterator).unwrap();
+        let program = emitter::emit_program(statements).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("teeny-output-{}", process::id()));
+        let path = dir.join("nested").join("hello.c");
+        write_output(&path, &program.source).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "#include <stdio.h>\nint main(void){\n\n    printf(\"hello world\\n\");\n    return 0;\n}\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_dump_tokens() {
+        let mut output = Vec::new();
+        dump_tokens("let x = 1\nprint x", &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"   0: Let
+   1: Identifier { name: "x" }
+   2: Equal
+   3: Number { value: 1 }
+   4: Newline
+   5: Print
+   6: Identifier { name: "x" }
+   7: EOF
+"#
+        );
+
+        let mut output = Vec::new();
+        assert!(dump_tokens("let x = @", &mut output).is_err());
+    }
+
+    #[test]
+    fn test_dump_ast() {
+        let mut output = Vec::new();
+        dump_ast(
+            "if 1 == 1 then\nwhile x < 2 repeat\nif x == 0 then\nprint x\nendif\nendwhile\nendif",
+            false,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Program\n  If 1 == 1\n    While x < 2\n      If x == 0\n        PrintExpression x\n"
+        );
+
+        let mut output = Vec::new();
+        dump_ast(
+            "let x = 1\nif x == 1 then\n  print x\nendif",
+            true,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Program\n  Let x = 1 @ 1:1\n  If x == 1 @ 2:1\n    PrintExpression x @ 3:3\n"
+        );
+
+        let mut output = Vec::new();
+        assert!(dump_ast("if 1 == then", false, &mut output).is_err());
+    }
+
+    #[test]
+    fn test_format_source() {
+        let input = "let x=1+2\nif x>1 then\nwhile x<10 repeat\nlet x = x+1\nendwhile\nendif\n";
+        let formatted = format_source(input).unwrap();
+        assert_eq!(
+            formatted,
+            "LET x = 1 + 2\nIF x > 1 THEN\n  WHILE x < 10 REPEAT\n    LET x = x + 1\n  ENDWHILE\nENDIF\n"
+        );
+        // Formatting is idempotent
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+
+        assert!(format_source("let x = ").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check("let x = 1\nif x == 1 then\nprint x\nendif\n").is_ok());
+
+        for (input, message) in [
+            (
+                "let x = 1\nprint @",
+                "line 2, col 7: unexpected character '@'",
+            ),
+            (
+                "let x = 1\nprint 1 2",
+                "line 2: Expected newline, found number 2",
+            ),
+            (
+                "let x = 1\nif x == 1 then\n\nprint y\nendif",
+                "line 4: use of undeclared variable 'y'",
+            ),
+            ("label a\nlabel a", "label 'a' defined more than once"),
+        ] {
+            assert_eq!(check(input).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_watcher() {
+        let path = env::temp_dir().join(format!("teeny-watch-{}.tiny", process::id()));
+        fs::write(&path, "print 1").unwrap();
+        let mut watcher = Watcher::new(&path, Duration::from_millis(10));
+        assert!(!watcher.changed());
+
+        // One change sets off one rebuild, however often it is polled
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let rebuilds = (0..3).filter(|_| watcher.changed()).count();
+        assert_eq!(rebuilds, 1);
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed());
+    }
+
+    #[test]
+    fn test_compile_to_cache() {
+        let dir = env::temp_dir().join(format!("teeny-compile-to-{}", process::id()));
+        let path = dir.join("watched.tiny");
+        let c_path = dir.join("watched.c");
+        fs::create_dir_all(&dir).unwrap();
+        let mut cache = lexer::LexCache::new();
+        for input in ["print 1", "print 1", "print 2"] {
+            fs::write(&path, input).unwrap();
+            compile_to(&path, &c_path, &mut cache).unwrap();
+            assert!(fs::read_to_string(&c_path)
+                .unwrap()
+                .contains(&format!("printf(\"%d\\n\", {});", &input[6..])));
+        }
+        // Only the unchanged rebuild is lexed from the cache
+        assert_eq!(cache.hits(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_provenance() {
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(
+            provenance(&["foo.tiny".to_string()]),
+            format!(
+                "/* Generated by teeny compiler v{} from foo.tiny */",
+                version
+            )
+        );
+        assert_eq!(
+            provenance(&["a.tiny".to_string(), "b*/.tiny".to_string()]),
+            format!(
+                "/* Generated by teeny compiler v{} from a.tiny, b* /.tiny */",
+                version
+            )
+        );
     }
 }
@@ -0,0 +1,642 @@
+use crate::emitter::{defined_labels, LineError};
+use crate::parser::*;
+use std::error::Error;
+
+// Optional passes over the AST, run between the parser and the emitter when
+// compiling with `--optimize`.
+//
+// Constant folding evaluates the parts of an expression that only involve
+// literals. Only results that fit in an i32 are folded, so they come out the
+// same whatever --int-type the program is compiled with. Dividing a literal by a literal zero
+// is an error, since the program could never run it. Anything else whose
+// result C leaves undefined or implementation specific (overflowing sums,
+// products and powers, or dividing a variable by zero) is left alone for the C
+// compiler to deal with, so --checked-arith still catches it at run time.
+//
+// Dead code elimination drops statements that directly follow a GOTO, since
+// the only way to reach them would be through a label.
+
+fn number(value: i32) -> Unary {
+    Unary::None(Box::new(Primary::Number(value)))
+}
+
+fn unary_value(unary: &Unary) -> Option<i32> {
+    match unary {
+        Unary::None(primary) | Unary::Plus(primary) => match primary.as_ref() {
+            Primary::Number(value) => Some(*value),
+            _ => None,
+        },
+        Unary::Minus(_) | Unary::BitNot(_) => None,
+    }
+}
+
+fn term_value(term: &Term) -> Option<i32> {
+    match term {
+        Term::SingleUnary(unary) => unary_value(unary),
+        Term::WithTail(..) => None,
+    }
+}
+
+fn expression_value(expression: &Expression) -> Option<i32> {
+    match expression {
+        Expression::SingleTerm(term) => term_value(term),
+        _ => None,
+    }
+}
+
+fn constant_expression(value: i32) -> Expression {
+    Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(number(value)))))
+}
+
+fn fold_primary(primary: Primary) -> Result<Primary, Box<dyn Error>> {
+    Ok(match primary {
+        Primary::Group(expression) => {
+            let expression = fold_constants(*expression)?;
+            match expression_value(&expression) {
+                Some(value) => Primary::Number(value),
+                None => Primary::Group(Box::new(expression)),
+            }
+        }
+        Primary::Index(ident, index) => Primary::Index(ident, Box::new(fold_constants(*index)?)),
+        Primary::Builtin(function, arguments) => Primary::Builtin(
+            function,
+            arguments
+                .into_iter()
+                .map(fold_constants)
+                .collect::<Result<Vec<Expression>, Box<dyn Error>>>()?,
+        ),
+        Primary::Power(base, exponent) => {
+            let base = fold_primary(*base)?;
+            let exponent = fold_unary(*exponent)?;
+            if let (Primary::Number(base), Some(exponent)) = (&base, unary_value(&exponent)) {
+                let power = u32::try_from(exponent)
+                    .ok()
+                    .and_then(|exponent| base.checked_pow(exponent));
+                if let Some(power) = power {
+                    return Ok(Primary::Number(power));
+                }
+            }
+            Primary::Power(Box::new(base), Box::new(exponent))
+        }
+        primary => primary,
+    })
+}
+
+fn fold_unary(unary: Unary) -> Result<Unary, Box<dyn Error>> {
+    Ok(match unary {
+        Unary::None(primary) => Unary::None(Box::new(fold_primary(*primary)?)),
+        Unary::Plus(primary) => Unary::Plus(Box::new(fold_primary(*primary)?)),
+        Unary::Minus(primary) => match fold_primary(*primary)? {
+            Primary::Number(i32::MIN) => Unary::Minus(Box::new(Primary::Group(Box::new(
+                constant_expression(i32::MIN),
+            )))),
+            Primary::Number(value) => number(-value),
+            primary => Unary::Minus(Box::new(primary)),
+        },
+        Unary::BitNot(primary) => match fold_primary(*primary)? {
+            Primary::Number(value) => number(!value),
+            primary => Unary::BitNot(Box::new(primary)),
+        },
+    })
+}
+
+// `left` divided by the constant `right` with `divide`, which is an error
+// when `right` is zero.
+fn divide_constants(
+    left: i32,
+    right: Option<i32>,
+    divide: fn(i32, i32) -> Option<i32>,
+) -> Result<Option<i32>, Box<dyn Error>> {
+    match right {
+        Some(0) => Err("division by zero in constant expression".into()),
+        right => Ok(right.and_then(|right| divide(left, right))),
+    }
+}
+
+fn fold_term(term: Term) -> Result<Term, Box<dyn Error>> {
+    let (first, tailunaries) = match term {
+        Term::SingleUnary(unary) => return Ok(Term::SingleUnary(Box::new(fold_unary(*unary)?))),
+        Term::WithTail(unary, tail) => {
+            let TermTail::Tail(tailunaries) = *tail;
+            (fold_unary(*unary)?, tailunaries)
+        }
+    };
+    let mut tail = tailunaries
+        .into_iter()
+        .map(|tailunary| {
+            Ok(match tailunary {
+                TailUnary::Multiply(unary) => TailUnary::Multiply(Box::new(fold_unary(*unary)?)),
+                TailUnary::Divide(unary) => TailUnary::Divide(Box::new(fold_unary(*unary)?)),
+                TailUnary::Modulo(unary) => TailUnary::Modulo(Box::new(fold_unary(*unary)?)),
+            })
+        })
+        .collect::<Result<Vec<TailUnary>, Box<dyn Error>>>()?;
+
+    // The operators are left associative, so only a run of constants at the
+    // start of the term can be combined.
+    let mut first = first;
+    while let Some(left) = unary_value(&first) {
+        let value = match tail.first() {
+            Some(TailUnary::Multiply(unary)) => {
+                unary_value(unary).and_then(|right| left.checked_mul(right))
+            }
+            Some(TailUnary::Divide(unary)) => {
+                divide_constants(left, unary_value(unary), i32::checked_div)?
+            }
+            Some(TailUnary::Modulo(unary)) => {
+                divide_constants(left, unary_value(unary), i32::checked_rem)?
+            }
+            None => None,
+        };
+        match value {
+            Some(value) => {
+                first = number(value);
+                tail.remove(0);
+            }
+            None => break,
+        }
+    }
+
+    Ok(if tail.is_empty() {
+        Term::SingleUnary(Box::new(first))
+    } else {
+        Term::WithTail(Box::new(first), Box::new(TermTail::Tail(tail)))
+    })
+}
+
+fn fold_operands(operands: Vec<Expression>) -> Result<Vec<Expression>, Box<dyn Error>> {
+    operands.into_iter().map(fold_constants).collect()
+}
+
+// Fold every constant sub-expression of `expression` down to a single number.
+pub fn fold_constants(expression: Expression) -> Result<Expression, Box<dyn Error>> {
+    Ok(match expression {
+        Expression::SingleTerm(term) => Expression::SingleTerm(Box::new(fold_term(*term)?)),
+        Expression::WithTail(term, tail) => {
+            let ExpressionTail::Tail(tailterms) = *tail;
+            let mut first = fold_term(*term)?;
+            let mut tail = tailterms
+                .into_iter()
+                .map(|tailterm| {
+                    Ok(match tailterm {
+                        TailTerm::Add(term) => TailTerm::Add(Box::new(fold_term(*term)?)),
+                        TailTerm::Subtract(term) => TailTerm::Subtract(Box::new(fold_term(*term)?)),
+                    })
+                })
+                .collect::<Result<Vec<TailTerm>, Box<dyn Error>>>()?;
+
+            while let Some(left) = term_value(&first) {
+                let value = match tail.first() {
+                    Some(TailTerm::Add(term)) => {
+                        term_value(term).and_then(|right| left.checked_add(right))
+                    }
+                    Some(TailTerm::Subtract(term)) => {
+                        term_value(term).and_then(|right| left.checked_sub(right))
+                    }
+                    None => None,
+                };
+                match value {
+                    Some(value) => {
+                        first = Term::SingleUnary(Box::new(number(value)));
+                        tail.remove(0);
+                    }
+                    None => break,
+                }
+            }
+
+            if tail.is_empty() {
+                Expression::SingleTerm(Box::new(first))
+            } else {
+                Expression::WithTail(Box::new(first), Box::new(ExpressionTail::Tail(tail)))
+            }
+        }
+        Expression::BitAnd(operands) => {
+            let operands = fold_operands(operands)?;
+            match operands
+                .iter()
+                .map(expression_value)
+                .collect::<Option<Vec<i32>>>()
+            {
+                Some(values) => constant_expression(values.into_iter().fold(!0, |a, b| a & b)),
+                None => Expression::BitAnd(operands),
+            }
+        }
+        Expression::BitOr(operands) => {
+            let operands = fold_operands(operands)?;
+            match operands
+                .iter()
+                .map(expression_value)
+                .collect::<Option<Vec<i32>>>()
+            {
+                Some(values) => constant_expression(values.into_iter().fold(0, |a, b| a | b)),
+                None => Expression::BitOr(operands),
+            }
+        }
+    })
+}
+
+// The value of `expression` if it only involves literals, or None if it reads
+// a variable or can't be worked out at compile time (dividing by zero, say).
+pub fn eval_const(expression: &Expression) -> Option<i32> {
+    fold_constants(expression.clone())
+        .ok()
+        .and_then(|expression| expression_value(&expression))
+}
+
+fn fold_comparisons(comparisons: Vec<Comparison>) -> Result<Vec<Comparison>, Box<dyn Error>> {
+    comparisons.into_iter().map(fold_comparison).collect()
+}
+
+fn fold_comparison(comparison: Comparison) -> Result<Comparison, Box<dyn Error>> {
+    let fold = |expression: Box<Expression>| fold_constants(*expression).map(Box::new);
+    Ok(match comparison {
+        Comparison::Equal(left, right) => Comparison::Equal(fold(left)?, fold(right)?),
+        Comparison::NotEqual(left, right) => Comparison::NotEqual(fold(left)?, fold(right)?),
+        Comparison::GreaterThan(left, right) => Comparison::GreaterThan(fold(left)?, fold(right)?),
+        Comparison::GreaterThanEqual(left, right) => {
+            Comparison::GreaterThanEqual(fold(left)?, fold(right)?)
+        }
+        Comparison::LessThan(left, right) => Comparison::LessThan(fold(left)?, fold(right)?),
+        Comparison::LessThanEqual(left, right) => {
+            Comparison::LessThanEqual(fold(left)?, fold(right)?)
+        }
+        Comparison::Chain(comparisons) => Comparison::Chain(fold_comparisons(comparisons)?),
+        Comparison::And(comparisons) => Comparison::And(fold_comparisons(comparisons)?),
+        Comparison::Or(comparisons) => Comparison::Or(fold_comparisons(comparisons)?),
+        Comparison::Not(comparison) => Comparison::Not(Box::new(fold_comparison(*comparison)?)),
+    })
+}
+
+fn fold_statement(statement: Statement) -> Result<Statement, Box<dyn Error>> {
+    Ok(match statement {
+        Statement::PrintExpression(expression) => {
+            Statement::PrintExpression(Box::new(fold_constants(*expression)?))
+        }
+        Statement::PrintComparison(comparison) => {
+            Statement::PrintComparison(fold_comparison(comparison)?)
+        }
+        Statement::Printn(Printable::Expression(expression)) => Statement::Printn(
+            Printable::Expression(Box::new(fold_constants(*expression)?)),
+        ),
+        Statement::Printn(Printable::Comparison(comparison)) => {
+            Statement::Printn(Printable::Comparison(fold_comparison(comparison)?))
+        }
+        Statement::If {
+            comparison,
+            body,
+            else_ifs,
+            else_body,
+        } => Statement::If {
+            comparison: fold_comparison(comparison)?,
+            body: optimize(body)?,
+            else_ifs: else_ifs
+                .into_iter()
+                .map(|(comparison, body)| Ok((fold_comparison(comparison)?, optimize(body)?)))
+                .collect::<Result<Vec<(Comparison, Vec<Statement>)>, Box<dyn Error>>>()?,
+            else_body: else_body.map(optimize).transpose()?,
+        },
+        Statement::While { comparison, body } => Statement::While {
+            comparison: fold_comparison(comparison)?,
+            body: optimize(body)?,
+        },
+        Statement::For {
+            ident,
+            start,
+            end,
+            body,
+        } => Statement::For {
+            ident,
+            start: fold_constants(start)?,
+            end: fold_constants(end)?,
+            body: optimize(body)?,
+        },
+        Statement::Let { ident, expression } => Statement::Let {
+            ident,
+            expression: fold_constants(expression)?,
+        },
+        Statement::LetComparison { ident, comparison } => Statement::LetComparison {
+            ident,
+            comparison: fold_comparison(comparison)?,
+        },
+        Statement::Exit(code) => Statement::Exit(code.map(fold_constants).transpose()?),
+        Statement::MultiLet(assignments) => Statement::MultiLet(
+            assignments
+                .into_iter()
+                .map(|(ident, expression)| Ok((ident, fold_constants(expression)?)))
+                .collect::<Result<Vec<(String, Expression)>, Box<dyn Error>>>()?,
+        ),
+        Statement::IndexAssign {
+            ident,
+            index,
+            expression,
+        } => Statement::IndexAssign {
+            ident,
+            index: fold_constants(index)?,
+            expression: fold_constants(expression)?,
+        },
+        Statement::CompoundAssign {
+            ident,
+            op,
+            expression,
+        } => Statement::CompoundAssign {
+            ident,
+            op,
+            expression: fold_constants(expression)?,
+        },
+        Statement::Call { name, arguments } => Statement::Call {
+            name,
+            arguments: arguments
+                .into_iter()
+                .map(fold_constants)
+                .collect::<Result<Vec<Expression>, Box<dyn Error>>>()?,
+        },
+        Statement::Spanned(span, statement) => Statement::Spanned(
+            span,
+            Box::new(
+                fold_statement(*statement).map_err(|error| LineError::wrap(span.line, error))?,
+            ),
+        ),
+        statement => statement,
+    })
+}
+
+// Whether a statement, or any statement inside it, declares something. C
+// declarations are hoisted to the top of main, and #defines and #includes to
+// the top of the file, so these take effect whether or not the statement ever
+// runs. The match is exhaustive so a new statement has
+// to be sorted into one group or the other.
+fn declares(statement: &Statement) -> bool {
+    match statement {
+        Statement::Let { .. }
+        | Statement::LetComparison { .. }
+        | Statement::MultiLet(_)
+        | Statement::InputString(_)
+        | Statement::ArrayDecl { .. }
+        | Statement::Const { .. }
+        | Statement::Include(_)
+        | Statement::For { .. } => true,
+        Statement::If {
+            body,
+            else_ifs,
+            else_body,
+            ..
+        } => {
+            body.iter().any(declares)
+                || else_ifs.iter().any(|(_, body)| body.iter().any(declares))
+                || else_body.iter().flatten().any(declares)
+        }
+        Statement::While { body, .. } => body.iter().any(declares),
+        Statement::Spanned(_, statement) => declares(statement),
+        Statement::PrintString(_)
+        | Statement::PrintExpression(_)
+        | Statement::PrintComparison(_)
+        | Statement::Printn(_)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Label(_)
+        | Statement::Goto(_)
+        | Statement::IndexAssign { .. }
+        | Statement::Input(_)
+        | Statement::Dump(_)
+        | Statement::CompoundAssign { .. }
+        | Statement::Exit(_)
+        | Statement::Call { .. } => false,
+    }
+}
+
+// Drop the statements between a GOTO and the next label, which nothing can
+// reach. A statement with a label anywhere inside it (say, in an IF body) can
+// be jumped into, so it is always kept, along with everything after it. So is
+// one that declares something, since later statements can still use what it
+// declares.
+fn remove_unreachable(statements: Vec<Statement>) -> Vec<Statement> {
+    let mut output = vec![];
+    let mut reachable = true;
+    for statement in statements {
+        if !defined_labels(std::slice::from_ref(&statement)).is_empty() {
+            reachable = true;
+        }
+        if !reachable {
+            if declares(&statement) {
+                output.push(statement);
+            }
+            continue;
+        }
+        if let Statement::Goto(_) = statement.unspanned() {
+            reachable = false;
+        }
+        output.push(statement);
+    }
+    output
+}
+
+// Run every optimization pass over a list of statements.
+pub fn optimize(statements: Vec<Statement>) -> Result<Vec<Statement>, Box<dyn Error>> {
+    let statements = statements
+        .into_iter()
+        .map(fold_statement)
+        .collect::<Result<Vec<Statement>, Box<dyn Error>>>()?;
+    Ok(remove_unreachable(statements))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emitter::{emit_program, emit_program_with_options, EmitOptions};
+    use crate::lexer::{lex, lex_with_spans, TokenIterator};
+    use crate::printer::format_expression;
+    use crate::types::{infer_types, IntType};
+
+    fn fold_source(input: &str) -> String {
+        let tokens = lex(&format!("print {}", input)).unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        match optimize(statements).unwrap().remove(0) {
+            Statement::PrintExpression(expression) => format_expression(&expression),
+            statement => panic!("expected a PRINT, found {:?}", statement),
+        }
+    }
+
+    fn eval_source(input: &str) -> Option<i32> {
+        let tokens = lex(&format!("print {}", input)).unwrap();
+        let AST::Program(mut statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        match statements.remove(0) {
+            Statement::PrintExpression(expression) => eval_const(&expression),
+            statement => panic!("expected a PRINT, found {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        assert_eq!(fold_source("2 + 3 * 4"), "14");
+        assert_eq!(fold_source("(1 + 2) * -(3 - 5)"), "6");
+        assert_eq!(fold_source("7 / 2 - 7 % 2 + 2 ^ 3"), "10");
+        assert_eq!(fold_source("~0 & 6 | 8"), "14");
+        assert_eq!(fold_source("-(0 - 3)"), "3");
+    }
+
+    #[test]
+    fn test_fold_partially_constant() {
+        assert_eq!(fold_source("x + 2 * 3"), "x + 6");
+        assert_eq!(fold_source("2 * 3 * x"), "6 * x");
+        assert_eq!(fold_source("1 + 2 - x + 4"), "3 - x + 4");
+        // Regrouping would change where an overflow happens, so this stays as is
+        assert_eq!(fold_source("x * 2 * 3"), "x * 2 * 3");
+        // As does anything that overflows
+        assert_eq!(fold_source("2147483647 + 1"), "2147483647 + 1");
+        assert_eq!(fold_source("1 + 2147483647 - 2"), "1 + 2147483647 - 2");
+        assert_eq!(fold_source("65536 * 65536 + 1"), "65536 * 65536 + 1");
+        assert_eq!(fold_source("-(0 - 2147483647 - 1)"), "-(-2147483648)");
+        assert_eq!(fold_source("(0 - 2147483647 - 1) / -1"), "-2147483648 / -1");
+        assert_eq!(fold_source("x * (0 - 3)"), "x * -3");
+        assert_eq!(fold_source("2 ^ x"), "2 ^ x");
+        // Dividing a variable by zero is C's problem, not ours
+        assert_eq!(fold_source("x / 0"), "x / 0");
+        assert_eq!(fold_source("x % (1 - 1)"), "x % 0");
+    }
+
+    #[test]
+    fn test_eval_const() {
+        assert_eq!(eval_source("2 + 3 * 4"), Some(14));
+        assert_eq!(eval_source("-(2 - 8) / 4"), Some(1));
+        assert_eq!(eval_source("x + 1"), None);
+        assert_eq!(eval_source("1 / (1 - 1)"), None);
+    }
+
+    #[test]
+    fn test_eval_const_associativity() {
+        // Left associative
+        assert_eq!(eval_source("10 - 2 - 3"), Some(5));
+        assert_eq!(eval_source("10 - (2 - 3)"), Some(11));
+        assert_eq!(eval_source("10 - 2 + 3"), Some(11));
+        assert_eq!(eval_source("100 / 10 / 2"), Some(5));
+        assert_eq!(eval_source("100 / (10 / 2)"), Some(20));
+        assert_eq!(eval_source("12 / 2 * 3"), Some(18));
+        assert_eq!(eval_source("17 % 5 % 3"), Some(2));
+        // Right associative
+        assert_eq!(eval_source("2 ^ 3 ^ 2"), Some(512));
+    }
+
+    #[test]
+    fn test_fold_division_by_zero() {
+        for input in [
+            "print 1 / 0",
+            "let x = 2 * 3 % (4 - 4)",
+            "if 1 == 1 then\nprint 5 / 0\nendif",
+        ] {
+            let tokens = lex(input).unwrap();
+            let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            assert_eq!(
+                optimize(statements).unwrap_err().to_string(),
+                "division by zero in constant expression",
+                "{}",
+                input
+            );
+        }
+
+        let (tokens, spans) = lex_with_spans("print 1\nprint 1 / 0").unwrap();
+        let AST::Program(statements) =
+            parse_with_spans(&mut TokenIterator::new(&tokens), &spans).unwrap();
+        assert_eq!(
+            optimize(statements).unwrap_err().to_string(),
+            "line 2: division by zero in constant expression"
+        );
+    }
+
+    #[test]
+    fn test_optimize_emits_folded_constant() {
+        let tokens = lex("print 2 + 3 * 4").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let result = emit_program(optimize(statements).unwrap()).unwrap();
+        assert!(result.source.contains("\n    printf(\"%d\\n\", 14);\n"));
+
+        let tokens = lex("print 0 - 2147483647 - 1").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let result = emit_program(optimize(statements).unwrap()).unwrap();
+        assert!(result
+            .source
+            .contains("\n    printf(\"%d\\n\", (-2147483647 - 1));\n"));
+    }
+
+    #[test]
+    fn test_optimize_keeps_wider_int_types() {
+        let options = EmitOptions {
+            int_type: IntType::Long,
+            ..EmitOptions::default()
+        };
+        let tokens = lex("print 2147483647 + 1\nprint 65536 * 65536").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let result = emit_program_with_options(optimize(statements).unwrap(), options).unwrap();
+        assert!(result
+            .source
+            .contains("\n    printf(\"%ld\\n\", 2147483647 + 1);\n"));
+        assert!(result
+            .source
+            .contains("\n    printf(\"%ld\\n\", 65536 * 65536);\n"));
+    }
+
+    #[test]
+    fn test_remove_unreachable() {
+        let tokens = lex("goto end\nprint 1\nlet x = 2\nlabel end\nprint 3\nwhile 1 == 1 repeat\ngoto end\nprint 4\nendwhile\ngoto end\nif 1 == 1 then\nlabel inside\nendif\nprint 5").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let goto_end = || Statement::Goto("end".to_string());
+        let result = optimize(statements).unwrap();
+        assert_eq!(result[0], goto_end());
+        // The LET still declares x, so only the PRINT goes
+        assert!(matches!(result[1], Statement::Let { .. }));
+        assert_eq!(result[2], Statement::Label("end".to_string()));
+        assert!(matches!(result[3], Statement::PrintExpression(_)));
+        match &result[4] {
+            Statement::While { body, .. } => assert_eq!(body, &[goto_end()]),
+            statement => panic!("expected a WHILE, found {:?}", statement),
+        }
+        assert_eq!(result[5], goto_end());
+        // The IF can be jumped into, so it and the PRINT after it stay
+        assert!(matches!(result[6], Statement::If { .. }));
+        assert!(matches!(result[7], Statement::PrintExpression(_)));
+        assert_eq!(result.len(), 8);
+    }
+
+    // Optimizing a program that compiles has to leave it compiling, with every
+    // variable and its type just as they were.
+    fn assert_keeps_types(input: &str) {
+        let tokens = lex(input).unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let types = infer_types(&statements).unwrap();
+        let optimized = optimize(statements).unwrap();
+        assert_eq!(infer_types(&optimized).unwrap(), types, "{}", input);
+        emit_program(optimized).unwrap();
+    }
+
+    #[test]
+    fn test_remove_unreachable_keeps_declarations() {
+        for input in [
+            "goto s\nlet x = 1\nlabel s\nprint x",
+            "goto s\nlet x = 1, y = x + 1\nlet s = \"hi\"\nlabel s\nprint x + y\nprint s",
+            "goto s\nlet f = 1 < 2\ninput$ name\nlabel s\nprint f\nprint name",
+            "goto s\nlet a = array 3\nlabel s\na[0] = 1\nprint a[0]",
+            "goto s\nconst n = 5\nconst greeting = \"hi\"\nlabel s\nprint n\nprint greeting",
+            "goto s\nfor i = 1 to 3\nprint i\nendfor\nlabel s\nprint i",
+            "goto s\nif 1 == 1 then\nwhile 1 == 1 repeat\nlet x = 2\nendwhile\nendif\nlabel s\nprint x",
+        ] {
+            assert_keeps_types(input);
+        }
+
+        let tokens = lex("goto s\n#include <math.h>\nlabel s\nprint 1").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let result = emit_program(optimize(statements).unwrap()).unwrap();
+        assert!(result.source.contains("#include <math.h>\n"));
+    }
+
+    #[test]
+    fn test_remove_unreachable_with_spans() {
+        let (tokens, spans) = lex_with_spans("goto end\nprint 1\nlabel end\nprint 2").unwrap();
+        let AST::Program(statements) =
+            parse_with_spans(&mut TokenIterator::new(&tokens), &spans).unwrap();
+        let result = optimize(statements).unwrap();
+        let result: Vec<&Statement> = result.iter().map(Statement::unspanned).collect();
+        assert_eq!(result[0], &Statement::Goto("end".to_string()));
+        assert_eq!(result[1], &Statement::Label("end".to_string()));
+        assert!(matches!(result[2], Statement::PrintExpression(_)));
+        assert_eq!(result.len(), 3);
+    }
+}
@@ -3,52 +3,323 @@
 // Parser for the following grammar:
 //
 // program ::= {statement}
-// statement ::= "PRINT" (expression | string) nl
-//     | "IF" comparison "THEN" nl {statement} "ENDIF" nl
+// statement ::= "PRINT" (comparison | expression | string) nl
+//     | "PRINTN" (comparison | expression | string) nl
+//     | "IF" comparison "THEN" statement
+//     | "IF" comparison "THEN" nl {statement}
+//         {"ELSEIF" comparison "THEN" nl {statement}}
+//         ["ELSE" nl {statement}] "ENDIF" nl
 //     | "WHILE" comparison "REPEAT" nl {statement} "ENDWHILE" nl
+//     | "FOR" ident "=" expression "TO" expression nl {statement} "ENDFOR" nl
+//     | "BREAK" nl
+//     | "CONTINUE" nl
 //     | "LABEL" ident nl
 //     | "GOTO" ident nl
-//     | "LET" ident "=" expression nl
+//     | "LET" ident "=" expression {"," ident "=" expression} nl
+//     | "LET" ident "=" comparison nl
+//     | "LET" ident "=" "ARRAY" number nl
+//     | "CONST" ident "=" (["-"] number | string) nl
+//     | ident "[" expression "]" "=" expression nl
 //     | "INPUT" ident nl
-// comparison ::= expression (("==" | "!=" | ">" | ">=" | "<" | "<=") expression)+
-// expression ::= term {( "-" | "+" ) term}
-// term ::= unary {( "/" | "*" ) unary}
-// unary ::= ["+" | "-"] primary
-// primary ::= number | ident
+//     | "INPUT$" ident nl
+//     | "DUMP" ident nl
+//     | ident ("+=" | "-=" | "*=" | "/=") expression nl
+//     | "CALL" ident "(" [expression {"," expression}] ")" nl
+//     | "EXIT" [expression] nl
+//     | "#include" header nl
+// comparison ::= conjunction {"OR" conjunction}
+// conjunction ::= negation {"AND" negation}
+// negation ::= "NOT" negation | relation
+// relation ::= expression (("==" | "!=" | ">" | ">=" | "<" | "<=") expression)+
+// expression ::= bitand {"|" bitand}
+// bitand ::= sum {"&" sum}
+// sum ::= term {( "-" | "+" ) term}
+// term ::= unary {( "/" | "*" | "%" ) unary}
+// unary ::= ["+" | "-" | "~"] power
+// power ::= primary ["^" unary]
+// primary ::= number | ident | ident "[" expression "]" | string | "(" expression ")"
+//     | builtin "(" [expression {"," expression}] ")"
+// builtin ::= "RANDOM" | "ABS" | "MIN" | "MAX"
 // nl ::= '\n'+
+//
+// FOR loops are inclusive of both bounds, so `FOR i = 1 TO 3` runs with i = 1, 2 and 3.
+//
+// A program starts running at `LABEL start` if it has one, and at the top otherwise.
+//
+// `RANDOM(n)` is a pseudo-random number from 0 to n - 1, seeded from the
+// clock when the program starts. `ABS(x)` is the absolute value of x, and
+// `MIN(a, b)` and `MAX(a, b)` the smaller and larger of a and b.
+//
+// Newlines are allowed after any binary operator and inside parentheses, so a
+// long expression can be continued on the next line: `LET x = 1 +` then `2`.
 
-use crate::lexer::{Token, TokenIterator};
+use crate::lexer::{Span, Token, TokenIterator};
 use std::error::Error;
-use std::iter::Peekable;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Tracing of every parse step, off unless asked for with `set_verbose`.
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+// How deeply blocks, parentheses, powers and NOTs may nest before parsing
+// gives up, so a pathological input is an error rather than a stack overflow.
+// Each level of parentheses costs several stack frames, and an unoptimized
+// build runs out of a 2MB thread stack at around 240 levels.
+static MAX_DEPTH: AtomicUsize = AtomicUsize::new(128);
+
+pub fn set_max_depth(depth: usize) {
+    MAX_DEPTH.store(depth, Ordering::Relaxed);
+}
+
+// The depth one level further in, or an error if that is too deep.
+fn nested(depth: usize) -> Result<usize, ParseError> {
+    if depth >= MAX_DEPTH.load(Ordering::Relaxed) {
+        return Err(ParseError::NestingTooDeep);
+    }
+    Ok(depth + 1)
+}
+
+// Trace lines go to stderr so they never end up mixed into emitted C.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if VERBOSE.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    // A token the grammar doesn't allow here, and a description of what it
+    // does allow. `found` is None if the tokens ran out.
+    UnexpectedToken {
+        found: Option<Token>,
+        expected: &'static str,
+    },
+    // LABEL, GOTO, LET, INPUT and FOR must be followed by a name
+    ExpectedIdentifier {
+        after: &'static str,
+    },
+    // A keyword where LET expects the name of a variable
+    ReservedWord {
+        word: Token,
+    },
+    // What was found where a comparison operator or the start of an
+    // expression should have been
+    ExpectedComparisonOperator {
+        found: Option<Token>,
+    },
+    ExpectedExpression {
+        found: Option<Token>,
+    },
+    // An IF, WHILE or FOR that reached the end of the input while still open
+    UnclosedBlock {
+        block: &'static str,
+        terminator: &'static str,
+    },
+    DuplicateElse,
+    ElseIfAfterElse,
+    NestingTooDeep,
+    // A built-in function given the wrong number of arguments
+    WrongArgumentCount {
+        function: Builtin,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken { found, expected } => match found {
+                Some(found) => write!(f, "Expected {}, found {}", expected, found),
+                None => write!(f, "Expected {}, found {}", expected, Token::EOF),
+            },
+            ParseError::ExpectedIdentifier { after } => {
+                write!(f, "Expected identifier after {}", after)
+            }
+            ParseError::ReservedWord { word } => write!(
+                f,
+                "cannot use reserved word '{}' as a variable name",
+                word.to_string().to_lowercase()
+            ),
+            ParseError::ExpectedComparisonOperator { found } => write!(
+                f,
+                "Expected comparison operator, found {}",
+                found.as_ref().unwrap_or(&Token::EOF)
+            ),
+            ParseError::ExpectedExpression { found } => write!(
+                f,
+                "Expected number or identifier, found {}",
+                found.as_ref().unwrap_or(&Token::EOF)
+            ),
+            ParseError::UnclosedBlock { block, terminator } => {
+                write!(f, "expected {} to close {}", terminator, block)
+            }
+            ParseError::DuplicateElse => write!(f, "Unexpected second ELSE in IF"),
+            ParseError::ElseIfAfterElse => write!(f, "Unexpected ELSEIF after ELSE in IF"),
+            ParseError::NestingTooDeep => write!(f, "nesting too deep"),
+            ParseError::WrongArgumentCount { function, found } => write!(
+                f,
+                "{} takes {} argument{}, found {}",
+                function.name(),
+                function.arity(),
+                if function.arity() == 1 { "" } else { "s" },
+                found
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
 
-#[derive(Debug, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AST {
     Program(Vec<Statement>),
 }
 
-#[derive(Debug, PartialEq)]
+impl Comparison {
+    // The operands and operator of a single `a <op> b` comparison.
+    pub fn relation(&self) -> Option<(&Expression, &'static str, &Expression)> {
+        match self {
+            Comparison::Equal(left, right) => Some((left, "==", right)),
+            Comparison::NotEqual(left, right) => Some((left, "!=", right)),
+            Comparison::GreaterThan(left, right) => Some((left, ">", right)),
+            Comparison::GreaterThanEqual(left, right) => Some((left, ">=", right)),
+            Comparison::LessThan(left, right) => Some((left, "<", right)),
+            Comparison::LessThanEqual(left, right) => Some((left, "<=", right)),
+            _ => None,
+        }
+    }
+}
+
+impl Statement {
+    // The statement itself, without any span wrapped around it.
+    pub fn unspanned(&self) -> &Statement {
+        match self {
+            Statement::Spanned(_, statement) => statement.unspanned(),
+            statement => statement,
+        }
+    }
+}
+
+// The operator of a compound assignment such as `x += 1`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssignOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl fmt::Display for AssignOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssignOp::Add => write!(f, "+="),
+            AssignOp::Subtract => write!(f, "-="),
+            AssignOp::Multiply => write!(f, "*="),
+            AssignOp::Divide => write!(f, "/="),
+        }
+    }
+}
+
+// Anything PRINT or PRINTN can write out
+#[derive(Debug, Clone, PartialEq)]
+pub enum Printable {
+    String(String),
+    Expression(Box<Expression>),
+    Comparison(Comparison),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     // Make print allow for both strings and expressions
     PrintString(String),
     PrintExpression(Box<Expression>),
+    PrintComparison(Comparison),
+    // Like the PRINT variants, without the trailing newline
+    Printn(Printable),
     If {
         comparison: Comparison,
         body: Vec<Statement>,
+        // Each ELSEIF's comparison and body, in order
+        else_ifs: Vec<(Comparison, Vec<Statement>)>,
+        else_body: Option<Vec<Statement>>,
     },
     While {
         comparison: Comparison,
         body: Vec<Statement>,
     },
+    For {
+        ident: String,
+        start: Expression,
+        end: Expression,
+        body: Vec<Statement>,
+    },
+    // Leave the innermost WHILE or FOR, or skip to its next iteration
+    Break,
+    Continue,
     Label(String),
     Goto(String),
     Let {
         ident: String,
         expression: Expression,
     },
+    // A LET of a comparison, which stores 1 if it holds and 0 if not
+    LetComparison {
+        ident: String,
+        comparison: Comparison,
+    },
+    // A name for a number or string that never changes. `value` is always a
+    // `Primary::Number` or `Primary::String`.
+    Const {
+        ident: String,
+        value: Primary,
+    },
+    // A LET of more than one variable, assigned left to right so later
+    // expressions see the earlier values
+    MultiLet(Vec<(String, Expression)>),
     Input(String),
+    // Read a line of text into a string variable
+    InputString(String),
+    // Print a variable's name along with its value, for debugging
+    Dump(String),
+    // Declare an array of `size` ints, all starting out as 0
+    ArrayDecl {
+        ident: String,
+        size: usize,
+    },
+    // Assign to one element of an array, e.g. `a[i] = x`
+    IndexAssign {
+        ident: String,
+        index: Expression,
+        expression: Expression,
+    },
+    // Update an existing variable in place, e.g. `x += 1`
+    CompoundAssign {
+        ident: String,
+        op: AssignOp,
+        expression: Expression,
+    },
+    // An `#include` for the C, e.g. of a header declaring CALLed functions
+    Include(String),
+    // Stop the program, with the given exit code or else 0
+    Exit(Option<Expression>),
+    // Call a C function declared in an included header, ignoring its result
+    Call {
+        name: String,
+        arguments: Vec<Expression>,
+    },
+    // A statement along with where it came from in the source, covering all of
+    // its tokens bar the newline that ends it. Only made by `parse_with_spans`.
+    Spanned(Span, Box<Statement>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Comparison {
     Equal(Box<Expression>, Box<Expression>),
     NotEqual(Box<Expression>, Box<Expression>),
@@ -56,314 +327,758 @@ pub enum Comparison {
     GreaterThanEqual(Box<Expression>, Box<Expression>),
     LessThan(Box<Expression>, Box<Expression>),
     LessThanEqual(Box<Expression>, Box<Expression>),
+    Chain(Vec<Comparison>),
+    // Two or more operands, kept flat like `Expression::BitOr` so a long
+    // chain can't overflow the stack
+    And(Vec<Comparison>),
+    Or(Vec<Comparison>),
+    Not(Box<Comparison>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     SingleTerm(Box<Term>),
     WithTail(Box<Term>, Box<ExpressionTail>),
+    // Two or more operands. These are kept in a flat list rather than nested,
+    // so a long chain can't overflow the stack of every pass that walks it.
+    BitAnd(Vec<Expression>),
+    BitOr(Vec<Expression>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ExpressionTail {
     Tail(Vec<TailTerm>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TailTerm {
     Add(Box<Term>),
     Subtract(Box<Term>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Term {
     SingleUnary(Box<Unary>),
     WithTail(Box<Unary>, Box<TermTail>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TermTail {
     Tail(Vec<TailUnary>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TailUnary {
     Multiply(Box<Unary>),
     Divide(Box<Unary>),
+    Modulo(Box<Unary>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Unary {
+    // A primary with no sign in front of it
+    None(Box<Primary>),
     Plus(Box<Primary>),
     Minus(Box<Primary>),
+    BitNot(Box<Primary>),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Primary {
     Number(i32),
     Ident(String),
+    // An element of an array: its name and the index
+    Index(String, Box<Expression>),
+    String(String),
+    Group(Box<Expression>),
+    // Base and exponent. Binds tighter than a leading sign, so -2 ^ 2 is -(2 ^ 2)
+    Power(Box<Primary>, Box<Unary>),
+    // A call to a built-in function, with as many arguments as it takes
+    Builtin(Builtin, Vec<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Random,
+    Abs,
+    Min,
+    Max,
+}
+
+impl Builtin {
+    // The function a name refers to when followed by '(', if any. Like
+    // keywords, names are matched in any case.
+    fn from_name(name: &str) -> Option<Builtin> {
+        match name.to_lowercase().as_str() {
+            "random" => Some(Builtin::Random),
+            "abs" => Some(Builtin::Abs),
+            "min" => Some(Builtin::Min),
+            "max" => Some(Builtin::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Random => "random",
+            Builtin::Abs => "abs",
+            Builtin::Min => "min",
+            Builtin::Max => "max",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Random | Builtin::Abs => 1,
+            Builtin::Min | Builtin::Max => 2,
+        }
+    }
+}
+
+pub fn parse(tokens: &mut TokenIterator) -> Result<AST, ParseError> {
+    parse_program(tokens, None)
+}
+
+// Like `parse`, but with every statement wrapped in a `Statement::Spanned`.
+// `spans` are the spans of all of the tokens `tokens` started out with, as
+// returned by `lex_with_spans`.
+pub fn parse_with_spans(tokens: &mut TokenIterator, spans: &[Span]) -> Result<AST, ParseError> {
+    parse_program(tokens, Some(spans))
 }
 
-pub fn parse(tokens: &mut Peekable<TokenIterator>) -> Result<AST, Box<dyn Error>> {
+fn parse_program(tokens: &mut TokenIterator, spans: Option<&[Span]>) -> Result<AST, ParseError> {
     let mut statements = vec![];
-    while let Some(token) = tokens.next() {
-        println!("AST--- Parsing token: {:?}", token);
+    while let Some(token) = tokens.peek() {
+        trace!("AST--- Parsing token: {:?}", token);
         match token {
-            Token::Print => {
-                let next = tokens.next();
-                println!("AST--- Parsing print: {:?}", next);
-                match next {
-                    Some(Token::String { value }) => {
-                        let contents = value.clone();
-                        statements.push(Statement::PrintString(contents));
-                    }
-                    _ => {
-                        println!("AST--- Parsing print expression");
-                        let expression = parse_expression(tokens)?;
-                        statements.push(Statement::PrintExpression(Box::new(expression)));
-                    }
-                }
-            }
-            Token::If => {
-                println!("AST--- Parsing if");
-                let comparison = parse_comparison(tokens)?;
-                let mut body = vec![];
-                while let Some(token) = tokens.peek() {
-                    println!("AST--- Parsing if body: {:?}", token);
-                    match token {
-                        Token::Endif => {
-                            tokens.next();
-                            break;
-                        }
-                        Token::Then => {
-                            tokens.next();
-                        }
-                        _ => {
-                            body.push(parse_statement(tokens)?);
-                        }
-                    }
-                }
-                statements.push(Statement::If { comparison, body });
-            }
-            Token::While => {
-                println!("AST--- Parsing while");
-                let comparison = parse_comparison(tokens)?;
-                let mut body = vec![];
-                while let Some(token) = tokens.peek() {
-                    println!("AST--- Parsing while body: {:?}", token);
-                    match token {
-                        Token::Endwhile => {
-                            tokens.next();
-                            break;
-                        }
-                        Token::Repeat => {
-                            tokens.next();
-                        }
-                        _ => {
-                            body.push(parse_statement(tokens)?);
-                        }
-                    }
-                }
-                statements.push(Statement::While { comparison, body });
+            // Blank lines between statements
+            Token::Newline => {
+                tokens.next();
             }
-            Token::Label { name } => {
-                println!("AST--- Parsing label with name: {:?}", name);
-                let name = match tokens.next() {
-                    Some(Token::Identifier { name }) => name,
-                    _ => return Err("Expected identifier after LABEL".into()),
-                };
-                statements.push(Statement::Label(name));
-            }
-            Token::Goto => {
-                println!("AST--- Parsing goto");
-                let name = match tokens.next() {
-                    Some(Token::Identifier { name }) => name,
-                    _ => return Err("Expected identifier after GOTO".into()),
-                };
-                statements.push(Statement::Goto(name));
-            }
-            Token::Let => {
-                println!("AST--- Parsing let");
-                let ident = match tokens.next() {
-                    Some(Token::Identifier { name }) => name,
-                    _ => return Err("Expected identifier after LET".into()),
-                };
-                match tokens.next() {
-                    Some(Token::Equal) => {}
-                    _ => return Err("Expected = after identifier in LET".into()),
-                }
-                let expression = parse_expression(tokens)?;
-                statements.push(Statement::Let { ident, expression });
-            }
-            Token::Input => {
-                println!("AST--- Parsing input");
-                let ident = match tokens.next() {
-                    Some(Token::Identifier { name }) => name,
-                    _ => return Err("Expected identifier after INPUT".into()),
-                };
-                statements.push(Statement::Input(ident));
-            }
-            _ => {
-                return Err(format!(
-                    "Unexpected token at AST: {:?} \nAST State: {:?}",
-                    token,
-                    AST::Program(statements)
-                )
-                .into())
+            Token::EOF => {
+                tokens.next();
+                break;
             }
+            _ => parse_into(tokens, spans, &mut statements, 0)?,
         }
     }
     Ok(AST::Program(statements))
 }
 
-fn parse_statement(tokens: &mut Peekable<TokenIterator>) -> Result<Statement, Box<dyn Error>> {
+// Parse one statement and the newlines after it onto the end of `body`.
+fn parse_into(
+    tokens: &mut TokenIterator,
+    spans: Option<&[Span]>,
+    body: &mut Vec<Statement>,
+    depth: usize,
+) -> Result<(), ParseError> {
+    let statement = parse_spanned(tokens, spans, depth)?;
+    parse_nl(tokens)?;
+    body.push(statement);
+    Ok(())
+}
+
+// Parse one statement, wrapped in its span if there are spans. The index of
+// the next token is however many spans there are, less the number of tokens
+// still to come.
+fn parse_spanned(
+    tokens: &mut TokenIterator,
+    spans: Option<&[Span]>,
+    depth: usize,
+) -> Result<Statement, ParseError> {
+    let first = spans.map(|spans| spans.len() - tokens.len());
+    let statement = parse_statement(tokens, spans, depth)?;
+    Ok(match (spans, first) {
+        (Some(spans), Some(first)) => {
+            let last = spans.len() - tokens.len() - 1;
+            let span = Span {
+                end: spans[last].end,
+                ..spans[first]
+            };
+            Statement::Spanned(span, Box::new(statement))
+        }
+        _ => statement,
+    })
+}
+
+// The name a LET assigns to and the '=' after it.
+fn parse_let_target(tokens: &mut TokenIterator) -> Result<String, ParseError> {
+    let ident = match tokens.next() {
+        Some(Token::Identifier { name }) => name,
+        Some(word) if word.is_keyword() => {
+            trace!("Unexpected token in STATEMENT: {:?}", word);
+            return Err(ParseError::ReservedWord { word });
+        }
+        _ => {
+            trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+            return Err(ParseError::ExpectedIdentifier { after: "LET" });
+        }
+    };
+    match tokens.next() {
+        Some(Token::Equal) => Ok(ident),
+        found => {
+            trace!("Unexpected token in STATEMENT: {:?}", found);
+            Err(ParseError::UnexpectedToken {
+                found,
+                expected: "'=' after identifier in LET",
+            })
+        }
+    }
+}
+
+// The THEN or REPEAT that has to follow the comparison opening a block, and
+// the newline after it.
+fn expect_block_start(
+    tokens: &mut TokenIterator,
+    keyword: Token,
+    expected: &'static str,
+) -> Result<(), ParseError> {
+    match tokens.next() {
+        Some(token) if token == keyword => parse_nl(tokens),
+        found => Err(ParseError::UnexpectedToken { found, expected }),
+    }
+}
+
+// Every statement ends in one or more newlines, or the end of the input.
+fn parse_nl(tokens: &mut TokenIterator) -> Result<(), ParseError> {
+    match tokens.peek() {
+        Some(Token::Newline) | Some(Token::EOF) | None => {}
+        Some(token) => {
+            return Err(ParseError::UnexpectedToken {
+                found: Some(token.clone()),
+                expected: "newline",
+            })
+        }
+    }
+    while let Some(Token::Newline) = tokens.peek() {
+        tokens.next();
+    }
+    Ok(())
+}
+
+// Look ahead, without consuming anything, to see whether the next tokens form
+// a comparison rather than a plain expression. Comparison operators bind looser
+// than arithmetic, so `x + 1 == 2` compares `x + 1` with `2`.
+fn starts_comparison(tokens: &TokenIterator, depth: usize) -> bool {
+    let mut lookahead = tokens.clone();
+    if let Some(Token::Not) = lookahead.peek() {
+        return true;
+    }
+    parse_expression(&mut lookahead, depth).is_ok()
+        && matches!(
+            lookahead.peek(),
+            Some(
+                Token::EqualEqual
+                    | Token::NotEqual
+                    | Token::GreaterThan
+                    | Token::GreaterThanEqual
+                    | Token::LessThan
+                    | Token::LessThanEqual
+            )
+        )
+}
+
+// The argument of a PRINT or PRINTN.
+fn parse_printable(tokens: &mut TokenIterator, depth: usize) -> Result<Printable, ParseError> {
+    if starts_comparison(tokens, depth) {
+        return Ok(Printable::Comparison(parse_comparison(tokens, depth)?));
+    }
+    match tokens.peek() {
+        Some(Token::String { value }) => {
+            let contents = value.clone();
+            tokens.next();
+            Ok(Printable::String(contents))
+        }
+        _ => {
+            let expression = parse_expression(tokens, depth)?;
+            Ok(Printable::Expression(Box::new(expression)))
+        }
+    }
+}
+
+fn parse_statement(
+    tokens: &mut TokenIterator,
+    spans: Option<&[Span]>,
+    depth: usize,
+) -> Result<Statement, ParseError> {
     let token = tokens.next();
-    println!("STATEMENT--- Parsing token: {:?}", token);
-    match token {
-        Some(Token::Print) => match tokens.peek() {
-            Some(Token::String { value }) => {
-                let contents = value.clone();
-                Ok(Statement::PrintString(contents))
-            }
-            _ => {
-                let expression = parse_expression(tokens)?;
-                Ok(Statement::PrintExpression(Box::new(expression)))
-            }
+    trace!("STATEMENT--- Parsing token: {:?}", token);
+    let statement = match token {
+        Some(Token::Print) => match parse_printable(tokens, depth)? {
+            Printable::String(contents) => Statement::PrintString(contents),
+            Printable::Expression(expression) => Statement::PrintExpression(expression),
+            Printable::Comparison(comparison) => Statement::PrintComparison(comparison),
         },
+        Some(Token::Printn) => Statement::Printn(parse_printable(tokens, depth)?),
         Some(Token::If) => {
-            let comparison = parse_comparison(tokens)?;
+            let comparison = parse_comparison(tokens, depth)?;
+            // A statement on the same line as THEN is the whole body
+            if let (Some(Token::Then), Some(next)) = (tokens.peek(), tokens.peek_nth(1)) {
+                if !matches!(next, Token::Newline | Token::EOF) {
+                    tokens.next();
+                    return Ok(Statement::If {
+                        comparison,
+                        body: vec![parse_spanned(tokens, spans, nested(depth)?)?],
+                        else_ifs: vec![],
+                        else_body: None,
+                    });
+                }
+            }
+            expect_block_start(tokens, Token::Then, "THEN after comparison in IF")?;
             let mut body = vec![];
-            while let Some(token) = tokens.peek() {
-                match token {
-                    Token::Endif => {
+            let mut else_ifs: Vec<(Comparison, Vec<Statement>)> = vec![];
+            let mut else_body: Option<Vec<Statement>> = None;
+            loop {
+                match tokens.peek() {
+                    None | Some(Token::EOF) => {
+                        return Err(ParseError::UnclosedBlock {
+                            block: "IF",
+                            terminator: "ENDIF",
+                        });
+                    }
+                    Some(Token::Endif) => {
                         tokens.next();
                         break;
                     }
+                    Some(Token::Newline) => {
+                        tokens.next();
+                    }
+                    Some(Token::Else) => {
+                        tokens.next();
+                        if else_body.is_some() {
+                            return Err(ParseError::DuplicateElse);
+                        }
+                        else_body = Some(vec![]);
+                    }
+                    Some(Token::ElseIf) => {
+                        tokens.next();
+                        if else_body.is_some() {
+                            return Err(ParseError::ElseIfAfterElse);
+                        }
+                        let comparison = parse_comparison(tokens, depth)?;
+                        expect_block_start(tokens, Token::Then, "THEN after comparison in ELSEIF")?;
+                        else_ifs.push((comparison, vec![]));
+                    }
                     _ => {
-                        body.push(parse_statement(tokens)?);
+                        // Statements go to whichever branch was opened last
+                        let body = match (else_body.as_mut(), else_ifs.last_mut()) {
+                            (Some(else_body), _) => else_body,
+                            (None, Some((_, else_if_body))) => else_if_body,
+                            (None, None) => &mut body,
+                        };
+                        parse_into(tokens, spans, body, nested(depth)?)?;
                     }
                 }
             }
-            Ok(Statement::If { comparison, body })
+            Statement::If {
+                comparison,
+                body,
+                else_ifs,
+                else_body,
+            }
         }
         Some(Token::While) => {
-            let comparison = parse_comparison(tokens)?;
+            let comparison = parse_comparison(tokens, depth)?;
+            expect_block_start(tokens, Token::Repeat, "REPEAT after comparison in WHILE")?;
             let mut body = vec![];
-            while let Some(token) = tokens.peek() {
-                match token {
-                    Token::Endwhile => {
+            loop {
+                match tokens.peek() {
+                    None | Some(Token::EOF) => {
+                        return Err(ParseError::UnclosedBlock {
+                            block: "WHILE",
+                            terminator: "ENDWHILE",
+                        });
+                    }
+                    Some(Token::Endwhile) => {
                         tokens.next();
                         break;
                     }
-                    _ => {
-                        body.push(parse_statement(tokens)?);
+                    Some(Token::Newline) => {
+                        tokens.next();
+                    }
+                    _ => parse_into(tokens, spans, &mut body, nested(depth)?)?,
+                }
+            }
+            Statement::While { comparison, body }
+        }
+        Some(Token::For) => {
+            let ident = match tokens.next() {
+                Some(Token::Identifier { name }) => name,
+                _ => {
+                    trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+                    return Err(ParseError::ExpectedIdentifier { after: "FOR" });
+                }
+            };
+            match tokens.next() {
+                Some(Token::Equal) => {}
+                found => {
+                    trace!("Unexpected token in STATEMENT: {:?}", found);
+                    return Err(ParseError::UnexpectedToken {
+                        found,
+                        expected: "'=' after identifier in FOR",
+                    });
+                }
+            }
+            let start = parse_expression(tokens, depth)?;
+            match tokens.next() {
+                Some(Token::To) => {}
+                found => {
+                    trace!("Unexpected token in STATEMENT: {:?}", found);
+                    return Err(ParseError::UnexpectedToken {
+                        found,
+                        expected: "TO after start expression in FOR",
+                    });
+                }
+            }
+            let end = parse_expression(tokens, depth)?;
+            let mut body = vec![];
+            loop {
+                match tokens.peek() {
+                    None | Some(Token::EOF) => {
+                        return Err(ParseError::UnclosedBlock {
+                            block: "FOR",
+                            terminator: "ENDFOR",
+                        });
+                    }
+                    Some(Token::Endfor) => {
+                        tokens.next();
+                        break;
+                    }
+                    Some(Token::Newline) => {
+                        tokens.next();
                     }
+                    _ => parse_into(tokens, spans, &mut body, nested(depth)?)?,
                 }
             }
-            Ok(Statement::While { comparison, body })
+            Statement::For {
+                ident,
+                start,
+                end,
+                body,
+            }
+        }
+        Some(Token::Break) => Statement::Break,
+        Some(Token::Exit) => match tokens.peek() {
+            Some(Token::Newline | Token::EOF) | None => Statement::Exit(None),
+            _ => Statement::Exit(Some(parse_expression(tokens, depth)?)),
+        },
+        Some(Token::Continue) => Statement::Continue,
+        Some(Token::Label) => {
+            let name = match tokens.next() {
+                Some(Token::Identifier { name }) => name,
+                _ => {
+                    trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+                    return Err(ParseError::ExpectedIdentifier { after: "LABEL" });
+                }
+            };
+            Statement::Label(name)
         }
-        Some(Token::Label { name }) => Ok(Statement::Label(name.clone())),
         Some(Token::Goto) => {
             let name = match tokens.next() {
                 Some(Token::Identifier { name }) => name,
                 _ => {
-                    println!("Unexpected token in STATEMENT: {:?}", tokens.peek());
-                    return Err("Expected identifier after GOTO".into());
+                    trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+                    return Err(ParseError::ExpectedIdentifier { after: "GOTO" });
                 }
             };
-            Ok(Statement::Goto(name))
+            Statement::Goto(name)
         }
         Some(Token::Let) => {
+            let ident = parse_let_target(tokens)?;
+            if let Some(Token::Array) = tokens.peek() {
+                tokens.next();
+                return match tokens.next() {
+                    Some(Token::Number { value }) if value > 0 => Ok(Statement::ArrayDecl {
+                        ident,
+                        size: value as usize,
+                    }),
+                    found => Err(ParseError::UnexpectedToken {
+                        found,
+                        expected: "a positive array size after ARRAY",
+                    }),
+                };
+            }
+            if starts_comparison(tokens, depth) {
+                let comparison = parse_comparison(tokens, depth)?;
+                return Ok(Statement::LetComparison { ident, comparison });
+            }
+            let expression = parse_expression(tokens, depth)?;
+            if tokens.peek() != Some(&Token::Comma) {
+                return Ok(Statement::Let { ident, expression });
+            }
+            let mut assignments = vec![(ident, expression)];
+            while let Some(Token::Comma) = tokens.peek() {
+                tokens.next();
+                let ident = parse_let_target(tokens)?;
+                assignments.push((ident, parse_expression(tokens, depth)?));
+            }
+            Statement::MultiLet(assignments)
+        }
+        Some(Token::Input) => {
+            let ident = match tokens.next() {
+                Some(Token::Identifier { name }) => name,
+                _ => {
+                    trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+                    return Err(ParseError::ExpectedIdentifier { after: "INPUT" });
+                }
+            };
+            Statement::Input(ident)
+        }
+        Some(Token::InputString) => {
+            let ident = match tokens.next() {
+                Some(Token::Identifier { name }) => name,
+                _ => {
+                    trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+                    return Err(ParseError::ExpectedIdentifier { after: "INPUT$" });
+                }
+            };
+            Statement::InputString(ident)
+        }
+        Some(Token::Dump) => {
+            let ident = match tokens.next() {
+                Some(Token::Identifier { name }) => name,
+                _ => {
+                    trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+                    return Err(ParseError::ExpectedIdentifier { after: "DUMP" });
+                }
+            };
+            Statement::Dump(ident)
+        }
+        Some(Token::Include { header }) => Statement::Include(header),
+        Some(Token::Const) => {
             let ident = match tokens.next() {
                 Some(Token::Identifier { name }) => name,
                 _ => {
-                    println!("Unexpected token in STATEMENT: {:?}", tokens.peek());
-                    return Err("Expected identifier after LET".into());
+                    trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+                    return Err(ParseError::ExpectedIdentifier { after: "CONST" });
                 }
             };
             match tokens.next() {
                 Some(Token::Equal) => {}
-                _ => {
-                    println!("Unexpected token in STATEMENT: {:?}", tokens.peek());
-                    return Err("Expected = after identifier in LET".into());
+                found => {
+                    return Err(ParseError::UnexpectedToken {
+                        found,
+                        expected: "'=' after identifier in CONST",
+                    })
                 }
             }
-            let expression = parse_expression(tokens)?;
-            Ok(Statement::Let { ident, expression })
+            let value = match tokens.next() {
+                Some(Token::Number { value }) => Primary::Number(value),
+                Some(Token::Minus) => match tokens.next() {
+                    Some(Token::Number { value }) => Primary::Number(-value),
+                    found => {
+                        return Err(ParseError::UnexpectedToken {
+                            found,
+                            expected: "a number after '-' in CONST",
+                        })
+                    }
+                },
+                Some(Token::String { value }) => Primary::String(value),
+                found => {
+                    return Err(ParseError::UnexpectedToken {
+                        found,
+                        expected: "a number or string in CONST",
+                    })
+                }
+            };
+            Statement::Const { ident, value }
         }
-        Some(Token::Input) => {
-            let ident = match tokens.next() {
+        Some(Token::Call) => {
+            let name = match tokens.next() {
                 Some(Token::Identifier { name }) => name,
                 _ => {
-                    println!("Unexpected token in STATEMENT: {:?}", tokens.peek());
-                    return Err("Expected identifier after INPUT".into());
+                    trace!("Unexpected token in STATEMENT: {:?}", tokens.peek());
+                    return Err(ParseError::ExpectedIdentifier { after: "CALL" });
                 }
             };
-            Ok(Statement::Input(ident))
+            let arguments = parse_arguments(tokens, depth)?;
+            Statement::Call { name, arguments }
         }
-        _ => {
-            println!("Unexpected token in STATEMENT: {:?}", tokens.peek());
-            Err("Unexpected token at root".into())
-        }
-    }
-}
-
-fn parse_comparison(tokens: &mut Peekable<TokenIterator>) -> Result<Comparison, Box<dyn Error>> {
-    println!("COMPARISON--- Parsing token: {:?}", tokens.peek());
-    let expression = parse_expression(tokens)?;
-    println!("COMPARISON--- Got Comparator: {:?}", tokens.peek());
-    let comparator = tokens.next();
-    println!("COMPARISON--- Parsing token: {:?}", tokens.peek());
-    let expression2 = parse_expression(tokens)?;
-    println!(
-        "COMPARISON: {:?} {:?} {:?}",
-        expression, comparator, expression2
-    );
-    match comparator {
-        Some(Token::EqualEqual) => Ok(Comparison::Equal(
-            Box::new(expression),
-            Box::new(expression2),
-        )),
-        Some(Token::NotEqual) => Ok(Comparison::NotEqual(
-            Box::new(expression),
-            Box::new(expression2),
-        )),
-        Some(Token::GreaterThan) => Ok(Comparison::GreaterThan(
-            Box::new(expression),
-            Box::new(expression2),
-        )),
-        Some(Token::GreaterThanEqual) => Ok(Comparison::GreaterThanEqual(
-            Box::new(expression),
-            Box::new(expression2),
-        )),
-        Some(Token::LessThan) => Ok(Comparison::LessThan(
-            Box::new(expression),
-            Box::new(expression2),
-        )),
-        Some(Token::LessThanEqual) => Ok(Comparison::LessThanEqual(
-            Box::new(expression),
-            Box::new(expression2),
-        )),
-        _ => Err("Expected comparison operator".into()),
-    }
-}
-
-fn parse_expression(tokens: &mut Peekable<TokenIterator>) -> Result<Expression, Box<dyn Error>> {
-    println!("EXPRESSION--- Parsing token: {:?}", tokens.peek());
-    let initialterm = parse_term(tokens)?;
+        Some(Token::Identifier { name: ident }) if tokens.peek() == Some(&Token::LBracket) => {
+            let index = parse_index(tokens, depth)?;
+            match tokens.next() {
+                Some(Token::Equal) => {}
+                found => {
+                    return Err(ParseError::UnexpectedToken {
+                        found,
+                        expected: "'=' after array element",
+                    })
+                }
+            }
+            let expression = parse_expression(tokens, depth)?;
+            Statement::IndexAssign {
+                ident,
+                index,
+                expression,
+            }
+        }
+        Some(Token::Identifier { name: ident }) => {
+            let op = match tokens.next() {
+                Some(Token::PlusEqual) => AssignOp::Add,
+                Some(Token::MinusEqual) => AssignOp::Subtract,
+                Some(Token::AsteriskEqual) => AssignOp::Multiply,
+                Some(Token::SlashEqual) => AssignOp::Divide,
+                found => {
+                    return Err(ParseError::UnexpectedToken {
+                        found,
+                        expected: "'+=', '-=', '*=' or '/=' after identifier",
+                    })
+                }
+            };
+            let expression = parse_expression(tokens, depth)?;
+            Statement::CompoundAssign {
+                ident,
+                op,
+                expression,
+            }
+        }
+        found => {
+            trace!("Unexpected token in STATEMENT: {:?}", found);
+            return Err(ParseError::UnexpectedToken {
+                found,
+                expected: "statement",
+            });
+        }
+    };
+    Ok(statement)
+}
+
+// An expression may continue onto the next line after a binary operator or
+// inside parentheses, so line breaks there are not statement ends.
+fn skip_newlines(tokens: &mut TokenIterator) {
+    while let Some(Token::Newline) = tokens.peek() {
+        tokens.next();
+    }
+}
+
+fn parse_comparison(tokens: &mut TokenIterator, depth: usize) -> Result<Comparison, ParseError> {
+    let mut operands = vec![parse_conjunction(tokens, depth)?];
+    while let Some(Token::Or) = tokens.peek() {
+        tokens.next();
+        skip_newlines(tokens);
+        operands.push(parse_conjunction(tokens, depth)?);
+    }
+    Ok(match operands.len() {
+        1 => operands.remove(0),
+        _ => Comparison::Or(operands),
+    })
+}
+
+fn parse_conjunction(tokens: &mut TokenIterator, depth: usize) -> Result<Comparison, ParseError> {
+    let mut operands = vec![parse_negation(tokens, depth)?];
+    while let Some(Token::And) = tokens.peek() {
+        tokens.next();
+        skip_newlines(tokens);
+        operands.push(parse_negation(tokens, depth)?);
+    }
+    Ok(match operands.len() {
+        1 => operands.remove(0),
+        _ => Comparison::And(operands),
+    })
+}
+
+fn parse_negation(tokens: &mut TokenIterator, depth: usize) -> Result<Comparison, ParseError> {
+    if let Some(Token::Not) = tokens.peek() {
+        tokens.next();
+        let comparison = parse_negation(tokens, nested(depth)?)?;
+        return Ok(Comparison::Not(Box::new(comparison)));
+    }
+    parse_relation(tokens, depth)
+}
+
+fn parse_relation(tokens: &mut TokenIterator, depth: usize) -> Result<Comparison, ParseError> {
+    trace!("COMPARISON--- Parsing token: {:?}", tokens.peek());
+    let mut expression = parse_expression(tokens, depth)?;
+    let mut comparisons = Vec::new();
+    loop {
+        trace!("COMPARISON--- Got Comparator: {:?}", tokens.peek());
+        let comparator = match tokens.peek() {
+            Some(
+                Token::EqualEqual
+                | Token::NotEqual
+                | Token::GreaterThan
+                | Token::GreaterThanEqual
+                | Token::LessThan
+                | Token::LessThanEqual,
+            ) => tokens.next(),
+            _ => break,
+        };
+        skip_newlines(tokens);
+        trace!("COMPARISON--- Parsing token: {:?}", tokens.peek());
+        let expression2 = parse_expression(tokens, depth)?;
+        trace!(
+            "COMPARISON: {:?} {:?} {:?}",
+            expression,
+            comparator,
+            expression2
+        );
+        let left = Box::new(expression);
+        let right = Box::new(expression2.clone());
+        comparisons.push(match comparator {
+            Some(Token::EqualEqual) => Comparison::Equal(left, right),
+            Some(Token::NotEqual) => Comparison::NotEqual(left, right),
+            Some(Token::GreaterThan) => Comparison::GreaterThan(left, right),
+            Some(Token::GreaterThanEqual) => Comparison::GreaterThanEqual(left, right),
+            Some(Token::LessThan) => Comparison::LessThan(left, right),
+            _ => Comparison::LessThanEqual(left, right),
+        });
+        // The right operand of one comparator is the left operand of the next,
+        // so `a < b < c` reads as `a < b` and `b < c`.
+        expression = expression2;
+    }
+    match comparisons.len() {
+        0 => Err(ParseError::ExpectedComparisonOperator {
+            found: tokens.peek().cloned(),
+        }),
+        1 => Ok(comparisons.remove(0)),
+        _ => Ok(Comparison::Chain(comparisons)),
+    }
+}
+
+fn parse_expression(tokens: &mut TokenIterator, depth: usize) -> Result<Expression, ParseError> {
+    trace!("EXPRESSION--- Parsing token: {:?}", tokens.peek());
+    let mut operands = vec![parse_bitand(tokens, depth)?];
+    while let Some(Token::Pipe) = tokens.peek() {
+        tokens.next();
+        skip_newlines(tokens);
+        operands.push(parse_bitand(tokens, depth)?);
+    }
+    Ok(match operands.len() {
+        1 => operands.remove(0),
+        _ => Expression::BitOr(operands),
+    })
+}
+
+fn parse_bitand(tokens: &mut TokenIterator, depth: usize) -> Result<Expression, ParseError> {
+    let mut operands = vec![parse_sum(tokens, depth)?];
+    while let Some(Token::Ampersand) = tokens.peek() {
+        tokens.next();
+        skip_newlines(tokens);
+        operands.push(parse_sum(tokens, depth)?);
+    }
+    Ok(match operands.len() {
+        1 => operands.remove(0),
+        _ => Expression::BitAnd(operands),
+    })
+}
+
+fn parse_sum(tokens: &mut TokenIterator, depth: usize) -> Result<Expression, ParseError> {
+    let initialterm = parse_term(tokens, depth)?;
     if tokens.peek() == Some(&Token::Plus) || tokens.peek() == Some(&Token::Minus) {
         let mut tailterms = Vec::new();
         while let Some(token) = tokens.peek() {
-            println!("EXPRESSION--- Parsing tail token: {:?}", token);
+            trace!("EXPRESSION--- Parsing tail token: {:?}", token);
             match token {
                 Token::Plus => {
                     tokens.next();
-                    let tailterm = parse_term(tokens)?;
+                    skip_newlines(tokens);
+                    let tailterm = parse_term(tokens, depth)?;
                     tailterms.push(TailTerm::Add(Box::new(tailterm)));
                 }
                 Token::Minus => {
                     tokens.next();
-                    let term = parse_term(tokens)?;
+                    skip_newlines(tokens);
+                    let term = parse_term(tokens, depth)?;
                     tailterms.push(TailTerm::Subtract(Box::new(term)));
                 }
                 _ => break,
@@ -378,24 +1093,32 @@ fn parse_expression(tokens: &mut Peekable<TokenIterator>) -> Result<Expression,
     }
 }
 
-fn parse_term(tokens: &mut Peekable<TokenIterator>) -> Result<Term, Box<dyn Error>> {
-    println!("TERM--- Parsing token: {:?}", tokens.peek());
-    let initialunary = parse_unary(tokens)?;
-    if tokens.peek() == Some(&Token::Asterisk) || tokens.peek() == Some(&Token::Slash) {
+fn parse_term(tokens: &mut TokenIterator, depth: usize) -> Result<Term, ParseError> {
+    trace!("TERM--- Parsing token: {:?}", tokens.peek());
+    let initialunary = parse_unary(tokens, depth)?;
+    if let Some(Token::Asterisk | Token::Slash | Token::Percent) = tokens.peek() {
         let mut tailunaries = Vec::new();
         while let Some(token) = tokens.peek() {
-            println!("TERM--- Parsing tail token: {:?}", token);
+            trace!("TERM--- Parsing tail token: {:?}", token);
             match token {
                 Token::Asterisk => {
                     tokens.next();
-                    let tailunary = parse_unary(tokens)?;
+                    skip_newlines(tokens);
+                    let tailunary = parse_unary(tokens, depth)?;
                     tailunaries.push(TailUnary::Multiply(Box::new(tailunary)));
                 }
                 Token::Slash => {
                     tokens.next();
-                    let tailunary = parse_unary(tokens)?;
+                    skip_newlines(tokens);
+                    let tailunary = parse_unary(tokens, depth)?;
                     tailunaries.push(TailUnary::Divide(Box::new(tailunary)));
                 }
+                Token::Percent => {
+                    tokens.next();
+                    skip_newlines(tokens);
+                    let tailunary = parse_unary(tokens, depth)?;
+                    tailunaries.push(TailUnary::Modulo(Box::new(tailunary)));
+                }
                 _ => break,
             }
         }
@@ -408,36 +1131,148 @@ fn parse_term(tokens: &mut Peekable<TokenIterator>) -> Result<Term, Box<dyn Erro
     }
 }
 
-fn parse_unary(tokens: &mut Peekable<TokenIterator>) -> Result<Unary, Box<dyn Error>> {
-    println!("UNARY--- Parsing token: {:?}", tokens.peek());
+fn parse_unary(tokens: &mut TokenIterator, depth: usize) -> Result<Unary, ParseError> {
+    trace!("UNARY--- Parsing token: {:?}", tokens.peek());
     let unary = tokens.peek();
     match unary {
         Some(Token::Plus) => {
             tokens.next();
-            let primary = parse_primary(tokens)?;
+            let primary = parse_power(tokens, depth)?;
             Ok(Unary::Plus(Box::new(primary)))
         }
+        // With nothing to subtract from, a minus before a literal is part of
+        // the literal. `-2 ^ 2` is still the negation of a power.
         Some(Token::Minus) => {
             tokens.next();
-            let primary = parse_primary(tokens)?;
-            Ok(Unary::Minus(Box::new(primary)))
+            match parse_power(tokens, depth)? {
+                Primary::Number(value) => Ok(Unary::None(Box::new(Primary::Number(-value)))),
+                primary => Ok(Unary::Minus(Box::new(primary))),
+            }
+        }
+        Some(Token::Tilde) => {
+            tokens.next();
+            let primary = parse_power(tokens, depth)?;
+            Ok(Unary::BitNot(Box::new(primary)))
         }
         _ => {
-            let primary = parse_primary(tokens)?;
-            Ok(Unary::Plus(Box::new(primary)))
+            let primary = parse_power(tokens, depth)?;
+            Ok(Unary::None(Box::new(primary)))
+        }
+    }
+}
+
+// Powers are right associative, so the exponent is parsed as a whole unary.
+fn parse_power(tokens: &mut TokenIterator, depth: usize) -> Result<Primary, ParseError> {
+    let base = parse_primary(tokens, depth)?;
+    match tokens.peek() {
+        Some(Token::Caret) => {
+            tokens.next();
+            skip_newlines(tokens);
+            let exponent = parse_unary(tokens, nested(depth)?)?;
+            Ok(Primary::Power(Box::new(base), Box::new(exponent)))
+        }
+        _ => Ok(base),
+    }
+}
+
+// The `[expression]` after an array's name.
+fn parse_index(tokens: &mut TokenIterator, depth: usize) -> Result<Expression, ParseError> {
+    tokens.next();
+    let index = parse_expression(tokens, nested(depth)?)?;
+    match tokens.next() {
+        Some(Token::RBracket) => Ok(index),
+        found => Err(ParseError::UnexpectedToken {
+            found,
+            expected: "']' to close '['",
+        }),
+    }
+}
+
+// The parenthesized, comma-separated arguments of a CALL.
+fn parse_arguments(
+    tokens: &mut TokenIterator,
+    depth: usize,
+) -> Result<Vec<Expression>, ParseError> {
+    match tokens.next() {
+        Some(Token::LParen) => {}
+        found => {
+            return Err(ParseError::UnexpectedToken {
+                found,
+                expected: "'(' after function name in CALL",
+            })
+        }
+    }
+    parse_argument_list(tokens, depth, "',' or ')' after argument in CALL")
+}
+
+// The arguments after a '(' up to and including the closing ')', with
+// `expected` describing what should follow each one.
+fn parse_argument_list(
+    tokens: &mut TokenIterator,
+    depth: usize,
+    expected: &'static str,
+) -> Result<Vec<Expression>, ParseError> {
+    let mut arguments = vec![];
+    skip_newlines(tokens);
+    if let Some(Token::RParen) = tokens.peek() {
+        tokens.next();
+        return Ok(arguments);
+    }
+    loop {
+        arguments.push(parse_expression(tokens, nested(depth)?)?);
+        skip_newlines(tokens);
+        match tokens.next() {
+            Some(Token::Comma) => skip_newlines(tokens),
+            Some(Token::RParen) => return Ok(arguments),
+            found => return Err(ParseError::UnexpectedToken { found, expected }),
         }
     }
 }
 
-fn parse_primary(tokens: &mut Peekable<TokenIterator>) -> Result<Primary, Box<dyn Error>> {
-    println!("PRIMARY--- Parsing token: {:?}", tokens.peek());
+fn parse_primary(tokens: &mut TokenIterator, depth: usize) -> Result<Primary, ParseError> {
+    trace!("PRIMARY--- Parsing token: {:?}", tokens.peek());
     let primary = tokens.next();
     match primary {
         Some(Token::Number { value }) => Ok(Primary::Number(value)),
+        Some(Token::Identifier { name }) if tokens.peek() == Some(&Token::LBracket) => {
+            let index = parse_index(tokens, depth)?;
+            Ok(Primary::Index(name, Box::new(index)))
+        }
+        Some(Token::Identifier { name }) if tokens.peek() == Some(&Token::LParen) => {
+            let Some(function) = Builtin::from_name(&name) else {
+                return Ok(Primary::Ident(name));
+            };
+            tokens.next();
+            let arguments = parse_argument_list(
+                tokens,
+                depth,
+                "',' or ')' after argument to built-in function",
+            )?;
+            if arguments.len() != function.arity() {
+                return Err(ParseError::WrongArgumentCount {
+                    function,
+                    found: arguments.len(),
+                });
+            }
+            Ok(Primary::Builtin(function, arguments))
+        }
         Some(Token::Identifier { name }) => Ok(Primary::Ident(name)),
-        _ => {
-            println!("Unexpected token at PRIMARY {:?}", tokens.peek());
-            Err("Expected number or identifier".into())
+        Some(Token::String { value }) => Ok(Primary::String(value)),
+        Some(Token::LParen) => {
+            skip_newlines(tokens);
+            let expression = parse_expression(tokens, nested(depth)?)?;
+            skip_newlines(tokens);
+            match tokens.next() {
+                Some(Token::RParen) => Ok(Primary::Group(Box::new(expression))),
+                found => Err(ParseError::UnexpectedToken {
+                    found,
+                    expected: "')' to close '('",
+                }),
+            }
+        }
+        found => {
+            trace!("Unexpected token at PRIMARY {:?}", found);
+            Err(ParseError::ExpectedExpression { found })
         }
     }
 }
@@ -470,7 +1305,7 @@ input x
             println!("Token: {:?}", token);
         }
 
-        let mut tokens = TokenIterator::new(&tokens).peekable();
+        let mut tokens = TokenIterator::new(&tokens);
         let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
@@ -480,29 +1315,31 @@ input x
                 Statement::If {
                     comparison: Comparison::Equal(
                         Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
-                            Box::new(Unary::Plus(Box::new(Primary::Number(1))))
+                            Box::new(Unary::None(Box::new(Primary::Number(1))))
                         )))),
                         Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
-                            Box::new(Unary::Plus(Box::new(Primary::Number(1))))
+                            Box::new(Unary::None(Box::new(Primary::Number(1))))
                         ))))
                     ),
                     body: vec![Statement::PrintExpression(Box::new(
-                        Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::Plus(
+                        Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
                             Box::new(Primary::Number(2))
                         )))))
-                    ))]
+                    ))],
+                    else_ifs: vec![],
+                    else_body: None,
                 },
                 Statement::While {
                     comparison: Comparison::Equal(
                         Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
-                            Box::new(Unary::Plus(Box::new(Primary::Number(1))))
+                            Box::new(Unary::None(Box::new(Primary::Number(1))))
                         )))),
                         Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
-                            Box::new(Unary::Plus(Box::new(Primary::Number(1))))
+                            Box::new(Unary::None(Box::new(Primary::Number(1))))
                         ))))
                     ),
                     body: vec![Statement::PrintExpression(Box::new(
-                        Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::Plus(
+                        Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
                             Box::new(Primary::Number(3))
                         )))))
                     ))]
@@ -512,11 +1349,1105 @@ input x
                 Statement::Let {
                     ident: "x".to_string(),
                     expression: Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(
-                        Unary::Plus(Box::new(Primary::Number(1)))
+                        Unary::None(Box::new(Primary::Number(1)))
                     ))))
                 },
                 Statement::Input("x".to_string())
             ])
         );
     }
+
+    #[test]
+    fn test_parse_chained_comparison() {
+        let tokens = lex("if 1 < 2 < 3 then\nendif").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let ast = parse(&mut tokens).unwrap();
+
+        let number = |value| {
+            Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
+                Box::new(Unary::None(Box::new(Primary::Number(value)))),
+            ))))
+        };
+        assert_eq!(
+            ast,
+            AST::Program(vec![Statement::If {
+                comparison: Comparison::Chain(vec![
+                    Comparison::LessThan(number(1), number(2)),
+                    Comparison::LessThan(number(2), number(3)),
+                ]),
+                body: vec![],
+                else_ifs: vec![],
+                else_body: None,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_parse_let() {
+        let five = Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
+            Box::new(Primary::Number(5)),
+        )))));
+
+        let tokens = lex("let x = 5").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        assert_eq!(
+            parse(&mut tokens).unwrap(),
+            AST::Program(vec![Statement::Let {
+                ident: "x".to_string(),
+                expression: five.clone(),
+            }])
+        );
+
+        // The same statement inside a body goes through parse_statement.
+        let tokens = lex("if 1 == 1 then\nlet x = 5\nendif").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+        let Statement::If { body, .. } = &statements[0] else {
+            panic!("expected an if statement");
+        };
+        assert_eq!(
+            body,
+            &vec![Statement::Let {
+                ident: "x".to_string(),
+                expression: five,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_label() {
+        let tokens = lex("label foo\nif 1 == 1 then\nlabel bar\nendif").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+        assert_eq!(statements[0], Statement::Label("foo".to_string()));
+        let Statement::If { body, .. } = &statements[1] else {
+            panic!("expected an if statement");
+        };
+        assert_eq!(body, &vec![Statement::Label("bar".to_string())]);
+    }
+
+    #[test]
+    fn test_parse_requires_newline() {
+        let tokens = lex("print 1 print 2").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        assert!(parse(&mut tokens).is_err());
+    }
+
+    #[test]
+    fn test_parse_if_else() {
+        let input =
+            "if 1 == 1 then\nlabel a\nelse\nif 2 == 2 then\nlabel b\nelse\nlabel c\nendif\nendif";
+        let tokens = lex(input).unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+        let Statement::If {
+            body, else_body, ..
+        } = &statements[0]
+        else {
+            panic!("expected an if statement");
+        };
+        assert_eq!(body, &vec![Statement::Label("a".to_string())]);
+        let Some(
+            [Statement::If {
+                body,
+                else_body: Some(inner_else),
+                ..
+            }],
+        ) = else_body.as_deref()
+        else {
+            panic!("expected a nested if/else in the else branch");
+        };
+        assert_eq!(body, &vec![Statement::Label("b".to_string())]);
+        assert_eq!(inner_else, &vec![Statement::Label("c".to_string())]);
+    }
+
+    #[test]
+    fn test_parse_for() {
+        let tokens = lex("for i = 1 to 10\nprint i\nendfor").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let primary = |primary| {
+            Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
+                Box::new(primary),
+            )))))
+        };
+        assert_eq!(
+            parse(&mut tokens).unwrap(),
+            AST::Program(vec![Statement::For {
+                ident: "i".to_string(),
+                start: primary(Primary::Number(1)),
+                end: primary(Primary::Number(10)),
+                body: vec![Statement::PrintExpression(Box::new(primary(
+                    Primary::Ident("i".to_string())
+                )))],
+            }])
+        );
+    }
+
+    #[test]
+    fn test_parse_modulo() {
+        let tokens = lex("print 7 % 3 * 2").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let number = |value| Box::new(Unary::None(Box::new(Primary::Number(value))));
+        assert_eq!(
+            parse(&mut tokens).unwrap(),
+            AST::Program(vec![Statement::PrintExpression(Box::new(
+                Expression::SingleTerm(Box::new(Term::WithTail(
+                    number(7),
+                    Box::new(TermTail::Tail(vec![
+                        TailUnary::Modulo(number(3)),
+                        TailUnary::Multiply(number(2)),
+                    ])),
+                )))
+            ))])
+        );
+    }
+
+    #[test]
+    fn test_parse_and_or() {
+        let tokens = lex("if 1 == 1 or 2 == 2 and 3 == 3 then\nendif").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+
+        let number = |value| {
+            Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
+                Box::new(Unary::None(Box::new(Primary::Number(value)))),
+            ))))
+        };
+        let equal = |value| Box::new(Comparison::Equal(number(value), number(value)));
+        let Statement::If { comparison, .. } = &statements[0] else {
+            panic!("expected an if statement");
+        };
+        assert_eq!(
+            comparison,
+            &Comparison::Or(vec![*equal(1), Comparison::And(vec![*equal(2), *equal(3)])])
+        );
+    }
+
+    #[test]
+    fn test_parse_not() {
+        let tokens = lex("if not x == 1 and 2 == 2 then\nendif").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let AST::Program(statements) = parse(&mut tokens).unwrap();
+
+        let primary = |primary| {
+            Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
+                Box::new(Unary::None(Box::new(primary))),
+            ))))
+        };
+        let Statement::If { comparison, .. } = &statements[0] else {
+            panic!("expected an if statement");
+        };
+        assert_eq!(
+            comparison,
+            &Comparison::And(vec![
+                Comparison::Not(Box::new(Comparison::Equal(
+                    primary(Primary::Ident("x".to_string())),
+                    primary(Primary::Number(1)),
+                ))),
+                Comparison::Equal(primary(Primary::Number(2)), primary(Primary::Number(2))),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_string_variable() {
+        let tokens = lex("let name = \"teeny\"\nprint name").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let primary = |primary| {
+            Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
+                Box::new(primary),
+            )))))
+        };
+        assert_eq!(
+            parse(&mut tokens).unwrap(),
+            AST::Program(vec![
+                Statement::Let {
+                    ident: "name".to_string(),
+                    expression: primary(Primary::String("teeny".to_string())),
+                },
+                Statement::PrintExpression(Box::new(primary(Primary::Ident("name".to_string())))),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_print_comparison() {
+        let tokens = lex("print x + 1 == 2\nprint x + 1").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let primary =
+            |primary| Box::new(Term::SingleUnary(Box::new(Unary::None(Box::new(primary)))));
+        let x_plus_1 = Expression::WithTail(
+            primary(Primary::Ident("x".to_string())),
+            Box::new(ExpressionTail::Tail(vec![TailTerm::Add(primary(
+                Primary::Number(1),
+            ))])),
+        );
+        assert_eq!(
+            parse(&mut tokens).unwrap(),
+            AST::Program(vec![
+                Statement::PrintComparison(Comparison::Equal(
+                    Box::new(x_plus_1.clone()),
+                    Box::new(Expression::SingleTerm(primary(Primary::Number(2)))),
+                )),
+                Statement::PrintExpression(Box::new(x_plus_1)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_group() {
+        let tokens = lex("print (1 + 2) * 3").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let number = |value| Box::new(Unary::None(Box::new(Primary::Number(value))));
+        let group = Expression::WithTail(
+            Box::new(Term::SingleUnary(number(1))),
+            Box::new(ExpressionTail::Tail(vec![TailTerm::Add(Box::new(
+                Term::SingleUnary(number(2)),
+            ))])),
+        );
+        assert_eq!(
+            parse(&mut tokens).unwrap(),
+            AST::Program(vec![Statement::PrintExpression(Box::new(
+                Expression::SingleTerm(Box::new(Term::WithTail(
+                    Box::new(Unary::None(Box::new(Primary::Group(Box::new(group))))),
+                    Box::new(TermTail::Tail(vec![TailUnary::Multiply(number(3))])),
+                )))
+            ))])
+        );
+
+        let tokens = lex("print (1 + 2").unwrap();
+        assert!(parse(&mut TokenIterator::new(&tokens)).is_err());
+    }
+
+    #[test]
+    fn test_parse_unterminated_block() {
+        for (input, message) in [
+            ("if 1 == 1 then\nprint 1\n", "expected ENDIF to close IF"),
+            ("if 1 == 1 then\n", "expected ENDIF to close IF"),
+            (
+                "while 1 == 1 repeat\nprint 1",
+                "expected ENDWHILE to close WHILE",
+            ),
+            ("for i = 1 to 3\nprint i\n", "expected ENDFOR to close FOR"),
+        ] {
+            let tokens = lex(input).unwrap();
+            let error = parse(&mut TokenIterator::new(&tokens)).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_parse_power() {
+        let tokens = lex("print 2 ^ 10 * -3 ^ 2 ^ 2").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let number = |value| Box::new(Primary::Number(value));
+        let power = |base, exponent| Primary::Power(number(base), Box::new(exponent));
+        assert_eq!(
+            parse(&mut tokens).unwrap(),
+            AST::Program(vec![Statement::PrintExpression(Box::new(
+                Expression::SingleTerm(Box::new(Term::WithTail(
+                    Box::new(Unary::None(Box::new(power(2, Unary::None(number(10)))))),
+                    Box::new(TermTail::Tail(vec![TailUnary::Multiply(Box::new(
+                        Unary::Minus(Box::new(power(
+                            3,
+                            Unary::None(Box::new(power(2, Unary::None(number(2)))))
+                        )))
+                    ))])),
+                )))
+            ))])
+        );
+    }
+
+    #[test]
+    fn test_parse_bitwise() {
+        let tokens = lex("print 1 | 5 & 3 + 1 | ~0").unwrap();
+        let mut tokens = TokenIterator::new(&tokens);
+        let number = |value| {
+            Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
+                Box::new(Unary::None(Box::new(Primary::Number(value)))),
+            ))))
+        };
+        let three_plus_one = Expression::WithTail(
+            Box::new(Term::SingleUnary(Box::new(Unary::None(Box::new(
+                Primary::Number(3),
+            ))))),
+            Box::new(ExpressionTail::Tail(vec![TailTerm::Add(Box::new(
+                Term::SingleUnary(Box::new(Unary::None(Box::new(Primary::Number(1))))),
+            ))])),
+        );
+        let not_zero = Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(
+            Unary::BitNot(Box::new(Primary::Number(0))),
+        ))));
+        assert_eq!(
+            parse(&mut tokens).unwrap(),
+            AST::Program(vec![Statement::PrintExpression(Box::new(
+                Expression::BitOr(vec![
+                    *number(1),
+                    Expression::BitAnd(vec![*number(5), three_plus_one]),
+                    not_zero,
+                ])
+            ))])
+        );
+    }
+
+    #[test]
+    fn test_parse_with_spans() {
+        let input = "let x = 1\n\nif x == 1 then\n  print x // shown\nendif\n";
+        let (tokens, spans) = crate::lexer::lex_with_spans(input).unwrap();
+        let AST::Program(statements) =
+            parse_with_spans(&mut TokenIterator::new(&tokens), &spans).unwrap();
+        let text = |span: &Span| &input[span.start..span.end];
+
+        let Statement::Spanned(span, statement) = &statements[1] else {
+            panic!("expected a spanned statement");
+        };
+        assert_eq!(text(span), "if x == 1 then\n  print x // shown\nendif");
+        assert_eq!((span.line, span.col), (3, 1));
+        let Statement::If { body, .. } = statement.as_ref() else {
+            panic!("expected an if statement");
+        };
+        let Statement::Spanned(span, _) = &body[0] else {
+            panic!("expected a spanned statement");
+        };
+        assert_eq!(text(span), "print x");
+        assert_eq!((span.line, span.col), (4, 3));
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_printn() {
+        let tokens = lex("printn \"x = \"\nprintn 1\nprintn 1 < 2\nprint \"\"").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let one = || {
+            Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
+                Box::new(Unary::None(Box::new(Primary::Number(1)))),
+            ))))
+        };
+        assert_eq!(
+            statements,
+            [
+                Statement::Printn(Printable::String("x = ".to_string())),
+                Statement::Printn(Printable::Expression(one())),
+                Statement::Printn(Printable::Comparison(Comparison::LessThan(
+                    one(),
+                    Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
+                        Box::new(Unary::None(Box::new(Primary::Number(2))))
+                    ))))
+                ))),
+                Statement::PrintString("".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_elseif() {
+        let tokens = lex("if x == 1 then\nlabel a\nelseif x == 2 then\nlabel b\nelseif x == 3 then\nelse\nlabel c\nendif").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let Statement::If {
+            body,
+            else_ifs,
+            else_body,
+            ..
+        } = &statements[0]
+        else {
+            panic!("expected an if statement");
+        };
+        let label = |name: &str| Statement::Label(name.to_string());
+        assert_eq!(body, &[label("a")]);
+        assert_eq!(else_ifs.len(), 2);
+        assert_eq!(else_ifs[0].1, [label("b")]);
+        assert!(else_ifs[1].1.is_empty());
+        assert_eq!(else_body.as_deref(), Some(&[label("c")][..]));
+
+        let tokens = lex("if 1 == 1 then\nelse\nelseif 2 == 2 then\nendif").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens))
+                .unwrap_err()
+                .to_string(),
+            "Unexpected ELSEIF after ELSE in IF"
+        );
+    }
+
+    #[test]
+    fn test_parse_error_names_token() {
+        let tokens = lex("print 1 2").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens))
+                .unwrap_err()
+                .to_string(),
+            "Expected newline, found number 2"
+        );
+    }
+
+    #[test]
+    fn test_parse_error_variants() {
+        let parse_error = |input: &str| {
+            let tokens = lex(input).unwrap();
+            parse(&mut TokenIterator::new(&tokens)).unwrap_err()
+        };
+        assert_eq!(
+            parse_error("let = 1"),
+            ParseError::ExpectedIdentifier { after: "LET" }
+        );
+        assert_eq!(
+            parse_error("let print = 1"),
+            ParseError::ReservedWord { word: Token::Print }
+        );
+        assert_eq!(
+            parse_error("let print = 1").to_string(),
+            "cannot use reserved word 'print' as a variable name"
+        );
+        assert_eq!(
+            parse_error("let x 1"),
+            ParseError::UnexpectedToken {
+                found: Some(Token::Number { value: 1 }),
+                expected: "'=' after identifier in LET",
+            }
+        );
+        assert_eq!(
+            parse_error("if 1 then\nendif"),
+            ParseError::ExpectedComparisonOperator {
+                found: Some(Token::Then)
+            }
+        );
+        assert_eq!(
+            parse_error("print *"),
+            ParseError::ExpectedExpression {
+                found: Some(Token::Asterisk)
+            }
+        );
+        assert!(matches!(
+            parse_error("while 1 < 2 repeat\n"),
+            ParseError::UnclosedBlock { block: "WHILE", .. }
+        ));
+        assert!(matches!(
+            parse_error("then"),
+            ParseError::UnexpectedToken {
+                found: Some(Token::Then),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_parse_multiline_expression() {
+        let parse_source = |input: &str| {
+            let tokens = lex(input).unwrap();
+            parse(&mut TokenIterator::new(&tokens))
+        };
+        assert_eq!(
+            parse_source("let x = 1 +\n2\nprint x").unwrap(),
+            parse_source("let x = 1 + 2\nprint x").unwrap()
+        );
+        assert_eq!(
+            parse_source("if x ==\n1 and\ny < (2 *\n3\n) then\nendif").unwrap(),
+            parse_source("if x == 1 and y < (2 * 3) then\nendif").unwrap()
+        );
+
+        // Without a trailing operator the line break still ends the statement
+        assert!(parse_source("let x = 1\n+ 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_explicit_plus() {
+        let unary = |input: &str| {
+            let tokens = lex(input).unwrap();
+            let AST::Program(mut statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            match statements.remove(0) {
+                Statement::Let {
+                    expression: Expression::SingleTerm(term),
+                    ..
+                } => match *term {
+                    Term::SingleUnary(unary) => *unary,
+                    term => panic!("expected a single unary, got {:?}", term),
+                },
+                statement => panic!("expected a let, got {:?}", statement),
+            }
+        };
+        let five = Box::new(Primary::Number(5));
+        assert_eq!(unary("let x = 5"), Unary::None(five.clone()));
+        assert_eq!(unary("let x = +5"), Unary::Plus(five));
+    }
+
+    #[test]
+    fn test_parse_negative_literal() {
+        let parse_let = |input: &str| {
+            let tokens = lex(input).unwrap();
+            let AST::Program(mut statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            match statements.remove(0) {
+                Statement::Let { expression, .. } => expression,
+                statement => panic!("expected a let, got {:?}", statement),
+            }
+        };
+        let unary = |unary| Box::new(Term::SingleUnary(Box::new(unary)));
+        let number = |value| Unary::None(Box::new(Primary::Number(value)));
+
+        assert_eq!(
+            parse_let("let x = -5"),
+            Expression::SingleTerm(unary(number(-5)))
+        );
+        assert_eq!(
+            parse_let("let x = 3 - 5"),
+            Expression::WithTail(
+                unary(number(3)),
+                Box::new(ExpressionTail::Tail(vec![TailTerm::Subtract(unary(
+                    number(5)
+                ))])),
+            )
+        );
+        assert_eq!(
+            parse_let("let x = 3 - -5"),
+            Expression::WithTail(
+                unary(number(3)),
+                Box::new(ExpressionTail::Tail(vec![TailTerm::Subtract(unary(
+                    number(-5)
+                ))])),
+            )
+        );
+
+        // Only a bare literal is folded; variables and powers keep their minus
+        assert_eq!(
+            parse_let("let x = -y"),
+            Expression::SingleTerm(unary(Unary::Minus(Box::new(Primary::Ident(
+                "y".to_string()
+            )))))
+        );
+        assert_eq!(
+            parse_let("let x = -2 ^ 2"),
+            Expression::SingleTerm(unary(Unary::Minus(Box::new(Primary::Power(
+                Box::new(Primary::Number(2)),
+                Box::new(number(2)),
+            )))))
+        );
+    }
+
+    #[test]
+    fn test_parse_nesting_too_deep() {
+        let nested_ifs =
+            |depth| "if 1 == 1 then\n".repeat(depth) + "print 1\n" + &"endif\n".repeat(depth);
+        let nested_groups = |depth| format!("print {}1{}", "(".repeat(depth), ")".repeat(depth));
+        let parse_source = |input: String| {
+            let tokens = lex(&input).unwrap();
+            parse(&mut TokenIterator::new(&tokens))
+        };
+
+        assert!(parse_source(nested_ifs(128)).is_ok());
+        assert!(parse_source(nested_groups(128)).is_ok());
+
+        for input in [nested_ifs(129), nested_groups(129), nested_ifs(100_000)] {
+            let error = parse_source(input).unwrap_err();
+            assert_eq!(error, ParseError::NestingTooDeep);
+            assert_eq!(error.to_string(), "nesting too deep");
+        }
+    }
+
+    #[test]
+    fn test_parse_compound_assign() {
+        let tokens = lex("x += 5").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap(),
+            AST::Program(vec![Statement::CompoundAssign {
+                ident: "x".to_string(),
+                op: AssignOp::Add,
+                expression: Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(
+                    Unary::None(Box::new(Primary::Number(5)))
+                )))),
+            }])
+        );
+
+        let tokens = lex("x = 5").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens))
+                .unwrap_err()
+                .to_string(),
+            "Expected '+=', '-=', '*=' or '/=' after identifier, found '='"
+        );
+    }
+
+    #[test]
+    fn test_parse_requires_then_and_repeat() {
+        for (input, message) in [
+            (
+                "if 1 == 1 print 1\nendif",
+                "Expected THEN after comparison in IF, found PRINT",
+            ),
+            (
+                "if 1 == 1\nprint 1\nendif",
+                "Expected THEN after comparison in IF, found newline",
+            ),
+            (
+                "if 1 == 1 then\nelseif 1 == 2\nendif",
+                "Expected THEN after comparison in ELSEIF, found newline",
+            ),
+            (
+                "while 1 == 1\nprint 1\nendwhile",
+                "Expected REPEAT after comparison in WHILE, found newline",
+            ),
+            (
+                "while 1 == 1 then\nendwhile",
+                "Expected REPEAT after comparison in WHILE, found THEN",
+            ),
+            (
+                "if 1 == 1 then\nthen\nendif",
+                "Expected statement, found THEN",
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            let error = parse(&mut TokenIterator::new(&tokens)).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_parse_single_line_if() {
+        let parse_source = |input: &str| {
+            let tokens = lex(input).unwrap();
+            parse(&mut TokenIterator::new(&tokens))
+        };
+        let number = |value| {
+            Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
+                Box::new(Unary::None(Box::new(Primary::Number(value)))),
+            ))))
+        };
+        let print = |value| Statement::PrintExpression(number(value));
+        let if_one = |body| Statement::If {
+            comparison: Comparison::Equal(number(1), number(1)),
+            body,
+            else_ifs: vec![],
+            else_body: None,
+        };
+
+        assert_eq!(
+            parse_source("if 1 == 1 then print 1\nprint 2").unwrap(),
+            AST::Program(vec![if_one(vec![print(1)]), print(2)])
+        );
+        // The block form still works, and means the same thing
+        assert_eq!(
+            parse_source("if 1 == 1 then\nprint 1\nendif\nprint 2").unwrap(),
+            AST::Program(vec![if_one(vec![print(1)]), print(2)])
+        );
+        assert_eq!(
+            parse_source("if 1 == 1 then if 1 == 1 then print 1").unwrap(),
+            AST::Program(vec![if_one(vec![if_one(vec![print(1)])])])
+        );
+        assert_eq!(
+            parse_source("while 1 == 1 repeat\nif 1 == 1 then print 1\nendwhile").unwrap(),
+            AST::Program(vec![Statement::While {
+                comparison: Comparison::Equal(number(1), number(1)),
+                body: vec![if_one(vec![print(1)])],
+            }])
+        );
+
+        // Only one statement fits on the line, so there's nothing for an ENDIF to close
+        assert!(parse_source("if 1 == 1 then print 1 endif").is_err());
+        assert!(parse_source("if 1 == 1 then print 1\nendif").is_err());
+
+        let input = "if 1 == 1 then print 1\nprint 2";
+        let (tokens, spans) = crate::lexer::lex_with_spans(input).unwrap();
+        let AST::Program(statements) =
+            parse_with_spans(&mut TokenIterator::new(&tokens), &spans).unwrap();
+        let Statement::Spanned(span, statement) = &statements[0] else {
+            panic!("expected a spanned statement");
+        };
+        assert_eq!((span.start, span.end), (0, 22));
+        let Statement::If { body, .. } = statement.as_ref() else {
+            panic!("expected an if statement");
+        };
+        assert!(matches!(&body[0], Statement::Spanned(span, _) if span.start == 15));
+    }
+
+    #[test]
+    fn test_parse_array() {
+        let tokens = lex("let a = array 10\na[i + 1] = 5\nprint a[2]").unwrap();
+        let expression = |primary| {
+            Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
+                Box::new(primary),
+            )))))
+        };
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap(),
+            AST::Program(vec![
+                Statement::ArrayDecl {
+                    ident: "a".to_string(),
+                    size: 10,
+                },
+                Statement::IndexAssign {
+                    ident: "a".to_string(),
+                    index: Expression::WithTail(
+                        Box::new(Term::SingleUnary(Box::new(Unary::None(Box::new(
+                            Primary::Ident("i".to_string())
+                        ))))),
+                        Box::new(ExpressionTail::Tail(vec![TailTerm::Add(Box::new(
+                            Term::SingleUnary(Box::new(Unary::None(Box::new(Primary::Number(1)))))
+                        ))])),
+                    ),
+                    expression: expression(Primary::Number(5)),
+                },
+                Statement::PrintExpression(Box::new(expression(Primary::Index(
+                    "a".to_string(),
+                    Box::new(expression(Primary::Number(2))),
+                )))),
+            ])
+        );
+
+        for (input, message) in [
+            (
+                "let a = array 0",
+                "Expected a positive array size after ARRAY, found number 0",
+            ),
+            (
+                "let a = array n",
+                "Expected a positive array size after ARRAY, found identifier 'n'",
+            ),
+            ("print a[1", "Expected ']' to close '[', found end of input"),
+            ("a[1] += 2", "Expected '=' after array element, found '+='"),
+        ] {
+            let tokens = lex(input).unwrap();
+            let error = parse(&mut TokenIterator::new(&tokens)).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_parse_break_continue() {
+        let tokens = lex("while 1 < 2 repeat\nbreak\ncontinue\nendwhile\nbreak").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let Statement::While { body, .. } = &statements[0] else {
+            panic!("expected a WHILE, got {:?}", statements[0]);
+        };
+        assert_eq!(body, &vec![Statement::Break, Statement::Continue]);
+        // Whether it is inside a loop is for the emitter to check
+        assert_eq!(statements[1], Statement::Break);
+
+        let tokens = lex("break 2").unwrap();
+        let error = parse(&mut TokenIterator::new(&tokens)).unwrap_err();
+        assert_eq!(error.to_string(), "Expected newline, found number 2");
+    }
+
+    #[test]
+    fn test_parse_builtin() {
+        let tokens = lex("let x = random(6)\nlet y = RANDOM(x + 1) * 2").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            statements[0],
+            Statement::Let {
+                ident: "x".to_string(),
+                expression: Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(
+                    Unary::None(Box::new(Primary::Builtin(
+                        Builtin::Random,
+                        vec![Expression::SingleTerm(Box::new(Term::SingleUnary(
+                            Box::new(Unary::None(Box::new(Primary::Number(6))))
+                        )))]
+                    )))
+                )))),
+            }
+        );
+        assert!(matches!(
+            &statements[1],
+            Statement::Let { expression: Expression::SingleTerm(term), .. }
+                if matches!(term.as_ref(), Term::WithTail(..))
+        ));
+
+        let primary = |primary| {
+            Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
+                Box::new(primary),
+            )))))
+        };
+        let parse_print = |input: &str| {
+            let tokens = lex(&format!("print {}", input)).unwrap();
+            let AST::Program(mut statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+            statements.remove(0)
+        };
+        assert_eq!(
+            parse_print("abs(x)"),
+            Statement::PrintExpression(Box::new(primary(Primary::Builtin(
+                Builtin::Abs,
+                vec![primary(Primary::Ident("x".to_string()))]
+            ))))
+        );
+        assert_eq!(
+            parse_print("min(1, x)"),
+            Statement::PrintExpression(Box::new(primary(Primary::Builtin(
+                Builtin::Min,
+                vec![
+                    primary(Primary::Number(1)),
+                    primary(Primary::Ident("x".to_string()))
+                ]
+            ))))
+        );
+        assert_eq!(
+            parse_print("Max(2, -3)"),
+            Statement::PrintExpression(Box::new(primary(Primary::Builtin(
+                Builtin::Max,
+                vec![primary(Primary::Number(2)), primary(Primary::Number(-3))]
+            ))))
+        );
+
+        for (input, message) in [
+            ("print random()", "random takes 1 argument, found 0"),
+            ("print random(1, 2)", "random takes 1 argument, found 2"),
+            ("print abs()", "abs takes 1 argument, found 0"),
+            ("print min(1)", "min takes 2 arguments, found 1"),
+            ("print max(1, 2, 3)", "max takes 2 arguments, found 3"),
+            (
+                "print random(1 2)",
+                "Expected ',' or ')' after argument to built-in function, found number 2",
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            let error = parse(&mut TokenIterator::new(&tokens)).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_parse_exit() {
+        let tokens = lex("exit\nif 1 == 1 then exit 1\nEXIT x + 1").unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(statements[0], Statement::Exit(None));
+        let Statement::If { body, .. } = &statements[1] else {
+            panic!("expected an IF, found {:?}", statements[1]);
+        };
+        assert_eq!(
+            body,
+            &vec![Statement::Exit(Some(Expression::SingleTerm(Box::new(
+                Term::SingleUnary(Box::new(Unary::None(Box::new(Primary::Number(1)))))
+            ))))]
+        );
+        assert!(matches!(
+            &statements[2],
+            Statement::Exit(Some(Expression::WithTail(..)))
+        ));
+
+        let tokens = lex("exit 1 2").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap_err(),
+            ParseError::UnexpectedToken {
+                found: Some(Token::Number { value: 2 }),
+                expected: "newline",
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_call() {
+        let expression = |primary| {
+            Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
+                Box::new(primary),
+            )))))
+        };
+        let tokens = lex("call foo(1, x)\ncall bar()").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap(),
+            AST::Program(vec![
+                Statement::Call {
+                    name: "foo".to_string(),
+                    arguments: vec![
+                        expression(Primary::Number(1)),
+                        expression(Primary::Ident("x".to_string())),
+                    ],
+                },
+                Statement::Call {
+                    name: "bar".to_string(),
+                    arguments: vec![],
+                },
+            ])
+        );
+
+        for (input, message) in [
+            ("call 1", "Expected identifier after CALL"),
+            (
+                "call foo 1",
+                "Expected '(' after function name in CALL, found number 1",
+            ),
+            (
+                "call foo(1 2)",
+                "Expected ',' or ')' after argument in CALL, found number 2",
+            ),
+            ("call foo(1,)", "Expected number or identifier, found ')'"),
+        ] {
+            let tokens = lex(input).unwrap();
+            let error = parse(&mut TokenIterator::new(&tokens)).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_parse_primary_error_names_consumed_token() {
+        // The error is about the token that couldn't start a primary, not
+        // the one after it
+        for (input, found, message) in [
+            (
+                "let x = 1 + = 2",
+                Token::Equal,
+                "Expected number or identifier, found '='",
+            ),
+            (
+                "print * 3",
+                Token::Asterisk,
+                "Expected number or identifier, found '*'",
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            let error = parse(&mut TokenIterator::new(&tokens)).unwrap_err();
+            assert_eq!(error, ParseError::ExpectedExpression { found: Some(found) });
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_parse_misplaced_then() {
+        for (input, message) in [
+            (
+                "if then\nendif",
+                "Expected number or identifier, found THEN",
+            ),
+            (
+                "if x == then\nendif",
+                "Expected number or identifier, found THEN",
+            ),
+            (
+                "if x then == 1 then",
+                "Expected comparison operator, found THEN",
+            ),
+            (
+                "print 1 + then",
+                "Expected number or identifier, found THEN",
+            ),
+            ("let x = (1 then", "Expected ')' to close '(', found THEN"),
+            (
+                "print (1 +",
+                "Expected number or identifier, found end of input",
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            let error = parse(&mut TokenIterator::new(&tokens)).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn test_parse_let_comparison() {
+        let expression = |primary| {
+            Box::new(Expression::SingleTerm(Box::new(Term::SingleUnary(
+                Box::new(Unary::None(Box::new(primary))),
+            ))))
+        };
+        let tokens = lex("let flag = x > 0\nlet both = x > 0 and y < 5").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap(),
+            AST::Program(vec![
+                Statement::LetComparison {
+                    ident: "flag".to_string(),
+                    comparison: Comparison::GreaterThan(
+                        expression(Primary::Ident("x".to_string())),
+                        expression(Primary::Number(0)),
+                    ),
+                },
+                Statement::LetComparison {
+                    ident: "both".to_string(),
+                    comparison: Comparison::And(vec![
+                        Comparison::GreaterThan(
+                            expression(Primary::Ident("x".to_string())),
+                            expression(Primary::Number(0)),
+                        ),
+                        Comparison::LessThan(
+                            expression(Primary::Ident("y".to_string())),
+                            expression(Primary::Number(5)),
+                        ),
+                    ]),
+                },
+            ])
+        );
+
+        // Only a LET of plain expressions can assign several variables
+        let tokens = lex("let flag = x > 0, y = 1").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap_err(),
+            ParseError::UnexpectedToken {
+                found: Some(Token::Comma),
+                expected: "newline",
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_multi_let() {
+        let expression = |primary| {
+            Expression::SingleTerm(Box::new(Term::SingleUnary(Box::new(Unary::None(
+                Box::new(primary),
+            )))))
+        };
+        let tokens = lex("let x = 1, y = x, z = 3").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap(),
+            AST::Program(vec![Statement::MultiLet(vec![
+                ("x".to_string(), expression(Primary::Number(1))),
+                ("y".to_string(), expression(Primary::Ident("x".to_string()))),
+                ("z".to_string(), expression(Primary::Number(3))),
+            ])])
+        );
+
+        for (input, error) in [
+            (
+                "let x = 1,",
+                ParseError::ExpectedIdentifier { after: "LET" },
+            ),
+            (
+                "let x = 1, y 2",
+                ParseError::UnexpectedToken {
+                    found: Some(Token::Number { value: 2 }),
+                    expected: "'=' after identifier in LET",
+                },
+            ),
+        ] {
+            let tokens = lex(input).unwrap();
+            assert_eq!(parse(&mut TokenIterator::new(&tokens)).unwrap_err(), error);
+        }
+    }
+
+    #[test]
+    fn test_parse_const() {
+        let tokens = lex("const x = 5\nconst y = -2\nconst s = \"hi\"").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap(),
+            AST::Program(vec![
+                Statement::Const {
+                    ident: "x".to_string(),
+                    value: Primary::Number(5),
+                },
+                Statement::Const {
+                    ident: "y".to_string(),
+                    value: Primary::Number(-2),
+                },
+                Statement::Const {
+                    ident: "s".to_string(),
+                    value: Primary::String("hi".to_string()),
+                },
+            ])
+        );
+
+        let tokens = lex("const x = y").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens))
+                .unwrap_err()
+                .to_string(),
+            "Expected a number or string in CONST, found identifier 'y'"
+        );
+    }
+
+    #[test]
+    fn test_parse_dump() {
+        let tokens = lex("dump x").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap(),
+            AST::Program(vec![Statement::Dump("x".to_string())])
+        );
+
+        let tokens = lex("dump 1").unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).unwrap_err(),
+            ParseError::ExpectedIdentifier { after: "DUMP" }
+        );
+    }
 }
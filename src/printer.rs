@@ -0,0 +1,534 @@
+use crate::emitter::escape_string;
+use crate::parser::*;
+
+// Render an AST as an indented tree, one statement per line, with bodies of
+// IF/WHILE/FOR nested one level deeper than the statement that owns them.
+// Expressions and comparisons are written back out in infix form, since
+// their Debug output is far too noisy to read.
+
+const INDENT: &str = "  ";
+
+fn format_primary(primary: &Primary) -> String {
+    match primary {
+        Primary::Number(number) => number.to_string(),
+        Primary::Ident(ident) => ident.to_string(),
+        Primary::Index(ident, index) => format!("{}[{}]", ident, format_expression(index)),
+        Primary::String(string) => format!("\"{}\"", escape_string(string)),
+        Primary::Group(expression) => format!("({})", format_expression(expression)),
+        Primary::Power(base, exponent) => {
+            format!("{} ^ {}", format_primary(base), format_unary(exponent))
+        }
+        Primary::Builtin(function, arguments) => {
+            format!("{}({})", function.name(), format_arguments(arguments))
+        }
+    }
+}
+
+fn format_unary(unary: &Unary) -> String {
+    match unary {
+        Unary::None(primary) => format_primary(primary),
+        Unary::Plus(primary) => format!("+{}", format_primary(primary)),
+        Unary::Minus(primary) => format!("-{}", format_primary(primary)),
+        Unary::BitNot(primary) => format!("~{}", format_primary(primary)),
+    }
+}
+
+fn format_term(term: &Term) -> String {
+    match term {
+        Term::SingleUnary(unary) => format_unary(unary),
+        Term::WithTail(unary, tail) => {
+            let TermTail::Tail(tailunaries) = tail.as_ref();
+            let mut output = format_unary(unary);
+            for tailunary in tailunaries {
+                let (operator, unary) = match tailunary {
+                    TailUnary::Multiply(unary) => ("*", unary),
+                    TailUnary::Divide(unary) => ("/", unary),
+                    TailUnary::Modulo(unary) => ("%", unary),
+                };
+                output.push_str(&format!(" {} {}", operator, format_unary(unary)));
+            }
+            output
+        }
+    }
+}
+
+pub fn format_expression(expression: &Expression) -> String {
+    match expression {
+        Expression::SingleTerm(term) => format_term(term),
+        Expression::WithTail(term, tail) => {
+            let ExpressionTail::Tail(tailterms) = tail.as_ref();
+            let mut output = format_term(term);
+            for tailterm in tailterms {
+                let (operator, term) = match tailterm {
+                    TailTerm::Add(term) => ("+", term),
+                    TailTerm::Subtract(term) => ("-", term),
+                };
+                output.push_str(&format!(" {} {}", operator, format_term(term)));
+            }
+            output
+        }
+        Expression::BitAnd(operands) => format_operands(operands, " & "),
+        Expression::BitOr(operands) => format_operands(operands, " | "),
+    }
+}
+
+fn format_operands(operands: &[Expression], operator: &str) -> String {
+    operands
+        .iter()
+        .map(format_expression)
+        .collect::<Vec<String>>()
+        .join(operator)
+}
+
+// The arguments of a CALL, separated by commas.
+fn format_arguments(arguments: &[Expression]) -> String {
+    arguments
+        .iter()
+        .map(format_expression)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// The `x = 1, y = 2` of a LET with more than one variable.
+fn format_assignments(assignments: &[(String, Expression)]) -> String {
+    assignments
+        .iter()
+        .map(|(ident, expression)| format!("{} = {}", ident, format_expression(expression)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn format_comparisons(comparisons: &[Comparison], operator: &str) -> String {
+    comparisons
+        .iter()
+        .map(format_comparison)
+        .collect::<Vec<String>>()
+        .join(operator)
+}
+
+pub fn format_comparison(comparison: &Comparison) -> String {
+    match comparison {
+        Comparison::Chain(comparisons) => {
+            // Every link after the first shares its left operand with the
+            // previous link's right operand, so only print the right side.
+            let mut output = format_comparison(&comparisons[0]);
+            for (_, operator, right) in comparisons[1..].iter().filter_map(Comparison::relation) {
+                output.push_str(&format!(" {} {}", operator, format_expression(right)));
+            }
+            output
+        }
+        Comparison::And(comparisons) => format_comparisons(comparisons, " AND "),
+        Comparison::Or(comparisons) => format_comparisons(comparisons, " OR "),
+        Comparison::Not(comparison) => format!("NOT {}", format_comparison(comparison)),
+        _ => {
+            let (left, operator, right) = comparison.relation().unwrap();
+            format!(
+                "{} {} {}",
+                format_expression(left),
+                operator,
+                format_expression(right)
+            )
+        }
+    }
+}
+
+fn print_body(body: &[Statement], depth: usize, output: &mut Vec<String>) {
+    for statement in body {
+        print_statement(statement, depth, output);
+    }
+}
+
+fn print_statement(statement: &Statement, depth: usize, output: &mut Vec<String>) {
+    let indent = INDENT.repeat(depth);
+    match statement {
+        Statement::PrintString(string) => {
+            output.push(format!("{}PrintString {:?}", indent, string))
+        }
+        Statement::PrintExpression(expression) => output.push(format!(
+            "{}PrintExpression {}",
+            indent,
+            format_expression(expression)
+        )),
+        Statement::PrintComparison(comparison) => output.push(format!(
+            "{}PrintComparison {}",
+            indent,
+            format_comparison(comparison)
+        )),
+        Statement::Printn(printable) => {
+            let printable = match printable {
+                Printable::String(string) => format!("{:?}", string),
+                Printable::Expression(expression) => format_expression(expression),
+                Printable::Comparison(comparison) => format_comparison(comparison),
+            };
+            output.push(format!("{}Printn {}", indent, printable))
+        }
+        Statement::If {
+            comparison,
+            body,
+            else_ifs,
+            else_body,
+        } => {
+            output.push(format!("{}If {}", indent, format_comparison(comparison)));
+            print_body(body, depth + 1, output);
+            for (comparison, body) in else_ifs {
+                output.push(format!(
+                    "{}ElseIf {}",
+                    indent,
+                    format_comparison(comparison)
+                ));
+                print_body(body, depth + 1, output);
+            }
+            if let Some(else_body) = else_body {
+                output.push(format!("{}Else", indent));
+                print_body(else_body, depth + 1, output);
+            }
+        }
+        Statement::While { comparison, body } => {
+            output.push(format!("{}While {}", indent, format_comparison(comparison)));
+            print_body(body, depth + 1, output);
+        }
+        Statement::For {
+            ident,
+            start,
+            end,
+            body,
+        } => {
+            output.push(format!(
+                "{}For {} = {} TO {}",
+                indent,
+                ident,
+                format_expression(start),
+                format_expression(end)
+            ));
+            print_body(body, depth + 1, output);
+        }
+        Statement::Break => output.push(format!("{}Break", indent)),
+        Statement::Exit(None) => output.push(format!("{}Exit", indent)),
+        Statement::Exit(Some(code)) => {
+            output.push(format!("{}Exit {}", indent, format_expression(code)))
+        }
+        Statement::Continue => output.push(format!("{}Continue", indent)),
+        Statement::Label(ident) => output.push(format!("{}Label {}", indent, ident)),
+        Statement::Goto(ident) => output.push(format!("{}Goto {}", indent, ident)),
+        Statement::Let { ident, expression } => output.push(format!(
+            "{}Let {} = {}",
+            indent,
+            ident,
+            format_expression(expression)
+        )),
+        Statement::LetComparison { ident, comparison } => output.push(format!(
+            "{}Let {} = {}",
+            indent,
+            ident,
+            format_comparison(comparison)
+        )),
+        Statement::MultiLet(assignments) => {
+            output.push(format!("{}Let {}", indent, format_assignments(assignments)))
+        }
+        Statement::Input(ident) => output.push(format!("{}Input {}", indent, ident)),
+        Statement::InputString(ident) => output.push(format!("{}InputString {}", indent, ident)),
+        Statement::ArrayDecl { ident, size } => {
+            output.push(format!("{}ArrayDecl {} {}", indent, ident, size))
+        }
+        Statement::IndexAssign {
+            ident,
+            index,
+            expression,
+        } => output.push(format!(
+            "{}IndexAssign {}[{}] = {}",
+            indent,
+            ident,
+            format_expression(index),
+            format_expression(expression)
+        )),
+        Statement::CompoundAssign {
+            ident,
+            op,
+            expression,
+        } => output.push(format!(
+            "{}CompoundAssign {} {} {}",
+            indent,
+            ident,
+            op,
+            format_expression(expression)
+        )),
+        Statement::Include(header) => output.push(format!("{}Include {}", indent, header)),
+        Statement::Dump(ident) => output.push(format!("{}Dump {}", indent, ident)),
+        Statement::Const { ident, value } => output.push(format!(
+            "{}Const {} = {}",
+            indent,
+            ident,
+            format_primary(value)
+        )),
+        Statement::Call { name, arguments } => output.push(format!(
+            "{}Call {}({})",
+            indent,
+            name,
+            format_arguments(arguments)
+        )),
+        Statement::Spanned(span, statement) => {
+            let first = output.len();
+            print_statement(statement, depth, output);
+            output[first].push_str(&format!(" @ {}:{}", span.line, span.col));
+        }
+    }
+}
+
+pub fn print_ast(ast: &AST) -> String {
+    let AST::Program(statements) = ast;
+    let mut output = vec!["Program".to_string()];
+    print_body(statements, 1, &mut output);
+    let mut output = output.join("\n");
+    output.push('\n');
+    output
+}
+
+fn unparse_body(body: &[Statement], depth: usize, output: &mut Vec<String>) {
+    for statement in body {
+        unparse_statement(statement, depth, output);
+    }
+}
+
+fn unparse_printable(printable: &Printable) -> String {
+    match printable {
+        Printable::String(string) => format!("\"{}\"", escape_string(string)),
+        Printable::Expression(expression) => format_expression(expression),
+        Printable::Comparison(comparison) => format_comparison(comparison),
+    }
+}
+
+fn unparse_statement(statement: &Statement, depth: usize, output: &mut Vec<String>) {
+    let indent = INDENT.repeat(depth);
+    match statement {
+        Statement::PrintString(string) => {
+            output.push(format!("{}PRINT \"{}\"", indent, escape_string(string)))
+        }
+        Statement::PrintExpression(expression) => {
+            output.push(format!("{}PRINT {}", indent, format_expression(expression)))
+        }
+        Statement::PrintComparison(comparison) => {
+            output.push(format!("{}PRINT {}", indent, format_comparison(comparison)))
+        }
+        Statement::Printn(printable) => {
+            output.push(format!("{}PRINTN {}", indent, unparse_printable(printable)))
+        }
+        Statement::If {
+            comparison,
+            body,
+            else_ifs,
+            else_body,
+        } => {
+            output.push(format!(
+                "{}IF {} THEN",
+                indent,
+                format_comparison(comparison)
+            ));
+            unparse_body(body, depth + 1, output);
+            for (comparison, body) in else_ifs {
+                output.push(format!(
+                    "{}ELSEIF {} THEN",
+                    indent,
+                    format_comparison(comparison)
+                ));
+                unparse_body(body, depth + 1, output);
+            }
+            if let Some(else_body) = else_body {
+                output.push(format!("{}ELSE", indent));
+                unparse_body(else_body, depth + 1, output);
+            }
+            output.push(format!("{}ENDIF", indent));
+        }
+        Statement::While { comparison, body } => {
+            output.push(format!(
+                "{}WHILE {} REPEAT",
+                indent,
+                format_comparison(comparison)
+            ));
+            unparse_body(body, depth + 1, output);
+            output.push(format!("{}ENDWHILE", indent));
+        }
+        Statement::For {
+            ident,
+            start,
+            end,
+            body,
+        } => {
+            output.push(format!(
+                "{}FOR {} = {} TO {}",
+                indent,
+                ident,
+                format_expression(start),
+                format_expression(end)
+            ));
+            unparse_body(body, depth + 1, output);
+            output.push(format!("{}ENDFOR", indent));
+        }
+        Statement::Break => output.push(format!("{}BREAK", indent)),
+        Statement::Exit(None) => output.push(format!("{}EXIT", indent)),
+        Statement::Exit(Some(code)) => {
+            output.push(format!("{}EXIT {}", indent, format_expression(code)))
+        }
+        Statement::Continue => output.push(format!("{}CONTINUE", indent)),
+        Statement::Label(ident) => output.push(format!("{}LABEL {}", indent, ident)),
+        Statement::Goto(ident) => output.push(format!("{}GOTO {}", indent, ident)),
+        Statement::Let { ident, expression } => output.push(format!(
+            "{}LET {} = {}",
+            indent,
+            ident,
+            format_expression(expression)
+        )),
+        Statement::LetComparison { ident, comparison } => output.push(format!(
+            "{}LET {} = {}",
+            indent,
+            ident,
+            format_comparison(comparison)
+        )),
+        Statement::MultiLet(assignments) => {
+            output.push(format!("{}LET {}", indent, format_assignments(assignments)))
+        }
+        Statement::Input(ident) => output.push(format!("{}INPUT {}", indent, ident)),
+        Statement::InputString(ident) => output.push(format!("{}INPUT$ {}", indent, ident)),
+        Statement::ArrayDecl { ident, size } => {
+            output.push(format!("{}LET {} = ARRAY {}", indent, ident, size))
+        }
+        Statement::IndexAssign {
+            ident,
+            index,
+            expression,
+        } => output.push(format!(
+            "{}{}[{}] = {}",
+            indent,
+            ident,
+            format_expression(index),
+            format_expression(expression)
+        )),
+        Statement::CompoundAssign {
+            ident,
+            op,
+            expression,
+        } => output.push(format!(
+            "{}{} {} {}",
+            indent,
+            ident,
+            op,
+            format_expression(expression)
+        )),
+        Statement::Include(header) => output.push(format!("{}#include {}", indent, header)),
+        Statement::Dump(ident) => output.push(format!("{}DUMP {}", indent, ident)),
+        Statement::Const { ident, value } => output.push(format!(
+            "{}CONST {} = {}",
+            indent,
+            ident,
+            format_primary(value)
+        )),
+        Statement::Call { name, arguments } => output.push(format!(
+            "{}CALL {}({})",
+            indent,
+            name,
+            format_arguments(arguments)
+        )),
+        Statement::Spanned(_, statement) => unparse_statement(statement, depth, output),
+    }
+}
+
+// Turn an AST back into teeny source: upper case keywords, one statement per
+// line and every block body indented one level. Parsing the result gives back
+// the same AST, so this doubles as a formatter.
+pub fn unparse(ast: &AST) -> String {
+    let AST::Program(statements) = ast;
+    let mut output = vec![];
+    unparse_body(statements, 0, &mut output);
+    let mut output = output.join("\n");
+    output.push('\n');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{lex, TokenIterator};
+
+    #[test]
+    fn test_print_ast() {
+        let input = r#"let x = 1 + 2 * -y
+if x > 1 and not x == 3 then
+if x < 10 then
+print "small"
+else
+print x % 2
+endif
+endif
+while 1 < x + 1 <= 5 repeat
+input x
+endwhile"#;
+        let tokens = lex(input).unwrap();
+        let ast = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(
+            print_ast(&ast),
+            r#"Program
+  Let x = 1 + 2 * -y
+  If x > 1 AND NOT x == 3
+    If x < 10
+      PrintString "small"
+    Else
+      PrintExpression x % 2
+  While 1 < x + 1 <= 5
+    Input x
+"#
+        );
+    }
+
+    #[test]
+    fn test_unparse() {
+        let input = r#"let x = 1 + 2 * -y
+let y = 1, z = y + 2
+if x > 1 and not x == 3 then
+if x < 10 then
+print "small\t\"quoted\"\n"
+elseif x < 20 then
+printn x % (2 - -3)
+else
+print x ^ 2 ^ 3 & 7 | ~x + +x
+endif
+endif
+label top
+while 1 < x + 1 <= 5 or x == 0 repeat
+input x
+for i = 1 to x
+print i >= 2
+endfor
+endwhile
+goto top
+"#;
+        let tokens = lex(input).unwrap();
+        let ast = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        let source = unparse(&ast);
+        assert_eq!(
+            source,
+            r#"LET x = 1 + 2 * -y
+LET y = 1, z = y + 2
+IF x > 1 AND NOT x == 3 THEN
+  IF x < 10 THEN
+    PRINT "small\t\"quoted\"\n"
+  ELSEIF x < 20 THEN
+    PRINTN x % (2 - -3)
+  ELSE
+    PRINT x ^ 2 ^ 3 & 7 | ~x + +x
+  ENDIF
+ENDIF
+LABEL top
+WHILE 1 < x + 1 <= 5 OR x == 0 REPEAT
+  INPUT x
+  FOR i = 1 TO x
+    PRINT i >= 2
+  ENDFOR
+ENDWHILE
+GOTO top
+"#
+        );
+
+        let tokens = lex(&source).unwrap();
+        let reparsed = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        assert_eq!(reparsed, ast);
+    }
+}
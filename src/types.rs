@@ -0,0 +1,516 @@
+use crate::emitter::LineError;
+use crate::parser::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// Work out the type of every variable before any C is emitted.
+//
+// A variable takes the type of its first assignment and keeps it for the
+// rest of the program, so `LET x = 1` followed by `LET x = "s"` is an error.
+// Statements are walked in source order, the same order the emitter declares
+// variables in, so using a variable before it is assigned is caught here too.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Int,
+    String,
+    // A fixed number of ints
+    Array(usize),
+}
+
+impl Type {
+    pub fn c_type(&self, int_type: IntType) -> &'static str {
+        match self {
+            Type::Int | Type::Array(_) => int_type.c_type(),
+            Type::String => "char *",
+        }
+    }
+
+    pub fn format_specifier(&self, int_type: IntType) -> &'static str {
+        match self {
+            Type::Int | Type::Array(_) => int_type.printf_specifier(),
+            Type::String => "%s",
+        }
+    }
+}
+
+// The C type teeny integers are emitted as. The specifiers go inside a C
+// string literal, so the int64_t ones close it around the <inttypes.h> macro.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum IntType {
+    #[default]
+    Int,
+    Long,
+    Int64,
+}
+
+impl IntType {
+    pub fn c_type(&self) -> &'static str {
+        match self {
+            IntType::Int => "int",
+            IntType::Long => "long",
+            IntType::Int64 => "int64_t",
+        }
+    }
+
+    pub fn printf_specifier(&self) -> &'static str {
+        match self {
+            IntType::Int => "%d",
+            IntType::Long => "%ld",
+            IntType::Int64 => "%\" PRId64 \"",
+        }
+    }
+
+    pub fn scanf_specifier(&self) -> &'static str {
+        match self {
+            IntType::Int => "%d",
+            IntType::Long => "%ld",
+            IntType::Int64 => "%\" SCNd64 \"",
+        }
+    }
+
+    // The function from <stdlib.h> that takes the absolute value of the type
+    pub fn abs_function(&self) -> &'static str {
+        match self {
+            IntType::Int => "abs",
+            IntType::Long => "labs",
+            IntType::Int64 => "llabs",
+        }
+    }
+
+    // The header the type needs on top of <stdio.h>, if any
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            IntType::Int | IntType::Long => None,
+            IntType::Int64 => Some("<inttypes.h>"),
+        }
+    }
+}
+
+impl FromStr for IntType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "int" => Ok(IntType::Int),
+            "long" => Ok(IntType::Long),
+            "int64_t" => Ok(IntType::Int64),
+            _ => Err(format!(
+                "unknown integer type '{}', expected int, long or int64_t",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "integer"),
+            Type::String => write!(f, "string"),
+            Type::Array(_) => write!(f, "array"),
+        }
+    }
+}
+
+// Every primary in an expression, in source order.
+pub(crate) fn primaries(expression: &Expression) -> Vec<&Primary> {
+    fn term_primaries(term: &Term) -> Vec<&Primary> {
+        let unaries = match term {
+            Term::SingleUnary(unary) => vec![unary.as_ref()],
+            Term::WithTail(unary, tail) => {
+                let TermTail::Tail(tailunaries) = tail.as_ref();
+                let mut unaries = vec![unary.as_ref()];
+                unaries.extend(tailunaries.iter().map(|tailunary| match tailunary {
+                    TailUnary::Multiply(unary)
+                    | TailUnary::Divide(unary)
+                    | TailUnary::Modulo(unary) => unary.as_ref(),
+                }));
+                unaries
+            }
+        };
+        unaries
+            .into_iter()
+            .map(|unary| match unary {
+                Unary::None(primary)
+                | Unary::Plus(primary)
+                | Unary::Minus(primary)
+                | Unary::BitNot(primary) => primary.as_ref(),
+            })
+            .collect()
+    }
+
+    match expression {
+        Expression::SingleTerm(term) => term_primaries(term),
+        Expression::WithTail(term, tail) => {
+            let ExpressionTail::Tail(tailterms) = tail.as_ref();
+            let mut output = term_primaries(term);
+            for tailterm in tailterms {
+                match tailterm {
+                    TailTerm::Add(term) | TailTerm::Subtract(term) => {
+                        output.extend(term_primaries(term))
+                    }
+                }
+            }
+            output
+        }
+        Expression::BitAnd(operands) | Expression::BitOr(operands) => {
+            operands.iter().flat_map(primaries).collect()
+        }
+    }
+}
+
+// The primary an expression consists of, if it is nothing but a bare primary.
+fn lone_primary(expression: &Expression) -> Option<&Primary> {
+    match expression {
+        Expression::SingleTerm(term) => match term.as_ref() {
+            Term::SingleUnary(unary) => match unary.as_ref() {
+                Unary::None(primary) | Unary::Plus(primary) => Some(primary),
+                Unary::Minus(_) | Unary::BitNot(_) => None,
+            },
+            Term::WithTail(..) => None,
+        },
+        Expression::WithTail(..) | Expression::BitAnd(..) | Expression::BitOr(..) => None,
+    }
+}
+
+pub fn primary_type(
+    types: &HashMap<String, Type>,
+    primary: &Primary,
+) -> Result<Type, Box<dyn Error>> {
+    match primary {
+        Primary::Number(_) => Ok(Type::Int),
+        Primary::String(_) => Ok(Type::String),
+        Primary::Group(expression) => expression_type(types, expression),
+        Primary::Power(base, exponent) => {
+            let (Unary::None(exponent)
+            | Unary::Plus(exponent)
+            | Unary::Minus(exponent)
+            | Unary::BitNot(exponent)) = exponent.as_ref();
+            for operand in [base, exponent] {
+                if primary_type(types, operand)? == Type::String {
+                    return Err("cannot use a string in an arithmetic expression".into());
+                }
+            }
+            Ok(Type::Int)
+        }
+        Primary::Ident(ident) => match types.get(ident) {
+            Some(Type::Array(_)) => {
+                Err(format!("cannot use array '{}' without an index", ident).into())
+            }
+            Some(ty) => Ok(*ty),
+            None => Err(format!("use of undeclared variable '{}'", ident).into()),
+        },
+        Primary::Index(ident, index) => {
+            array_type(types, ident)?;
+            if expression_type(types, index)? != Type::Int {
+                return Err("array index must be an integer".into());
+            }
+            Ok(Type::Int)
+        }
+        Primary::Builtin(function, arguments) => {
+            for argument in arguments {
+                if expression_type(types, argument)? != Type::Int {
+                    return Err(format!("{} takes integer arguments", function.name()).into());
+                }
+            }
+            Ok(Type::Int)
+        }
+    }
+}
+
+// Check that `ident` names an array, and hand back its type.
+fn array_type(types: &HashMap<String, Type>, ident: &str) -> Result<Type, Box<dyn Error>> {
+    match types.get(ident) {
+        Some(ty @ Type::Array(_)) => Ok(*ty),
+        Some(_) => Err(format!("'{}' is not an array", ident).into()),
+        None => Err(format!("use of undeclared variable '{}'", ident).into()),
+    }
+}
+
+// A bare string or string variable is a string; anything else is
+// arithmetic, where every operand has to be an integer.
+pub fn expression_type(
+    types: &HashMap<String, Type>,
+    expression: &Expression,
+) -> Result<Type, Box<dyn Error>> {
+    if let Some(primary) = lone_primary(expression) {
+        return primary_type(types, primary);
+    }
+    for primary in primaries(expression) {
+        if primary_type(types, primary)? == Type::String {
+            return Err("cannot use a string in an arithmetic expression".into());
+        }
+    }
+    Ok(Type::Int)
+}
+
+// Check an expression that has to be an integer, such as a comparison operand.
+fn check_int_expression(
+    types: &HashMap<String, Type>,
+    expression: &Expression,
+) -> Result<(), Box<dyn Error>> {
+    if expression_type(types, expression)? != Type::Int {
+        return Err("expected an integer expression but found a string".into());
+    }
+    Ok(())
+}
+
+fn check_comparison(
+    types: &HashMap<String, Type>,
+    comparison: &Comparison,
+) -> Result<(), Box<dyn Error>> {
+    match comparison {
+        Comparison::Chain(comparisons)
+        | Comparison::And(comparisons)
+        | Comparison::Or(comparisons) => comparisons
+            .iter()
+            .try_for_each(|comparison| check_comparison(types, comparison)),
+        Comparison::Not(comparison) => check_comparison(types, comparison),
+        // Strings can be tested for equality, but have no order
+        Comparison::Equal(left, right) | Comparison::NotEqual(left, right) => {
+            if expression_type(types, left)? != expression_type(types, right)? {
+                return Err("cannot compare a string with an integer".into());
+            }
+            Ok(())
+        }
+        Comparison::GreaterThan(left, right)
+        | Comparison::GreaterThanEqual(left, right)
+        | Comparison::LessThan(left, right)
+        | Comparison::LessThanEqual(left, right) => {
+            for operand in [left, right] {
+                if expression_type(types, operand)? == Type::String {
+                    return Err("only == and != can compare strings".into());
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn check_printable(
+    types: &HashMap<String, Type>,
+    printable: &Printable,
+) -> Result<(), Box<dyn Error>> {
+    match printable {
+        Printable::String(_) => Ok(()),
+        Printable::Expression(expression) => expression_type(types, expression).map(|_| ()),
+        Printable::Comparison(comparison) => check_comparison(types, comparison),
+    }
+}
+
+// Give `ident` type `ty` if this is its first assignment, or check that `ty`
+// matches the type it already has.
+fn assign(types: &mut HashMap<String, Type>, ident: &str, ty: Type) -> Result<(), Box<dyn Error>> {
+    match types.get(ident) {
+        Some(existing) if *existing != ty => Err(format!(
+            "cannot assign {} value to {} variable '{}'",
+            ty, existing, ident
+        )
+        .into()),
+        Some(_) => Ok(()),
+        None => {
+            types.insert(ident.to_string(), ty);
+            Ok(())
+        }
+    }
+}
+
+fn check_statement(
+    types: &mut HashMap<String, Type>,
+    statement: &Statement,
+) -> Result<(), Box<dyn Error>> {
+    match statement {
+        Statement::PrintString(_) => {}
+        Statement::PrintExpression(expression) => {
+            expression_type(types, expression)?;
+        }
+        Statement::PrintComparison(comparison) => check_comparison(types, comparison)?,
+        Statement::Printn(printable) => check_printable(types, printable)?,
+        Statement::If {
+            comparison,
+            body,
+            else_ifs,
+            else_body,
+        } => {
+            check_comparison(types, comparison)?;
+            extend_types(types, body)?;
+            for (comparison, body) in else_ifs {
+                check_comparison(types, comparison)?;
+                extend_types(types, body)?;
+            }
+            if let Some(else_body) = else_body {
+                extend_types(types, else_body)?;
+            }
+        }
+        Statement::While { comparison, body } => {
+            check_comparison(types, comparison)?;
+            extend_types(types, body)?;
+        }
+        Statement::For {
+            ident,
+            start,
+            end,
+            body,
+        } => {
+            check_int_expression(types, start)?;
+            assign(types, ident, Type::Int)?;
+            check_int_expression(types, end)?;
+            extend_types(types, body)?;
+        }
+        Statement::Break
+        | Statement::Continue
+        | Statement::Label(_)
+        | Statement::Goto(_)
+        | Statement::Exit(None)
+        | Statement::Include(_) => {}
+        Statement::Let { ident, expression } => {
+            let ty = expression_type(types, expression)?;
+            assign(types, ident, ty)?;
+        }
+        Statement::LetComparison { ident, comparison } => {
+            check_comparison(types, comparison)?;
+            assign(types, ident, Type::Int)?;
+        }
+        Statement::Const { ident, value } => {
+            let ty = primary_type(types, value)?;
+            assign(types, ident, ty)?;
+        }
+        Statement::MultiLet(assignments) => {
+            for (ident, expression) in assignments {
+                let ty = expression_type(types, expression)?;
+                assign(types, ident, ty)?;
+            }
+        }
+        Statement::ArrayDecl { ident, size } => {
+            if types.contains_key(ident) {
+                return Err(format!("'{}' is already declared", ident).into());
+            }
+            types.insert(ident.to_string(), Type::Array(*size));
+        }
+        Statement::IndexAssign {
+            ident,
+            index,
+            expression,
+        } => {
+            primary_type(
+                types,
+                &Primary::Index(ident.clone(), Box::new(index.clone())),
+            )?;
+            check_int_expression(types, expression)?;
+        }
+        // Only an integer that has already been assigned can be updated
+        Statement::CompoundAssign {
+            ident,
+            op,
+            expression,
+        } => {
+            if primary_type(types, &Primary::Ident(ident.clone()))? != Type::Int {
+                return Err(format!("cannot use {} on string variable '{}'", op, ident).into());
+            }
+            check_int_expression(types, expression)?;
+        }
+        Statement::Exit(Some(code)) => check_int_expression(types, code)?,
+        Statement::Call { arguments, .. } => {
+            for argument in arguments {
+                expression_type(types, argument)?;
+            }
+        }
+        Statement::Dump(ident) => {
+            primary_type(types, &Primary::Ident(ident.clone()))?;
+        }
+        // INPUT reads whatever type the variable already has, so it can't
+        // be what introduces it
+        Statement::Input(ident) => match types.get(ident) {
+            Some(Type::Int | Type::String) => {}
+            Some(Type::Array(_)) => {
+                return Err(format!("cannot INPUT into array '{}'", ident).into())
+            }
+            None => return Err(format!("INPUT into undeclared variable '{}'", ident).into()),
+        },
+        Statement::InputString(ident) => assign(types, ident, Type::String)?,
+        Statement::Spanned(span, statement) => {
+            check_statement(types, statement).map_err(|error| LineError::wrap(span.line, error))?
+        }
+    }
+    Ok(())
+}
+
+// Add the variables `statements` assign to an existing table, checking every
+// expression against it along the way.
+pub fn extend_types(
+    types: &mut HashMap<String, Type>,
+    statements: &[Statement],
+) -> Result<(), Box<dyn Error>> {
+    statements
+        .iter()
+        .try_for_each(|statement| check_statement(types, statement))
+}
+
+// The type of every variable in a program.
+pub fn infer_types(statements: &[Statement]) -> Result<HashMap<String, Type>, Box<dyn Error>> {
+    let mut types = HashMap::new();
+    extend_types(&mut types, statements)?;
+    Ok(types)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{lex, TokenIterator};
+
+    fn infer_source(input: &str) -> Result<HashMap<String, Type>, Box<dyn Error>> {
+        let tokens = lex(input).unwrap();
+        let AST::Program(statements) = parse(&mut TokenIterator::new(&tokens)).unwrap();
+        infer_types(&statements)
+    }
+
+    #[test]
+    fn test_infer_types() {
+        let types = infer_source(
+            "let x = 1\nlet s = \"hi\"\nlet t = s\nlet a = array 4\nwhile x < 3 repeat\nlet y = x * 2\nendwhile\nfor i = 1 to 3\nendfor\ninput$ name",
+        )
+        .unwrap();
+        assert_eq!(
+            types,
+            HashMap::from([
+                ("x".to_string(), Type::Int),
+                ("s".to_string(), Type::String),
+                ("t".to_string(), Type::String),
+                ("a".to_string(), Type::Array(4)),
+                ("y".to_string(), Type::Int),
+                ("i".to_string(), Type::Int),
+                ("name".to_string(), Type::String),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_infer_type_errors() {
+        for (input, message) in [
+            (
+                "let x = 1\nlet x = \"s\"",
+                "cannot assign string value to integer variable 'x'",
+            ),
+            (
+                "let x = 1\nif 1 == 1 then\ninput$ x\nendif",
+                "cannot assign string value to integer variable 'x'",
+            ),
+            ("input x\nlet x = 1", "INPUT into undeclared variable 'x'"),
+            ("let a = array 2\ninput a", "cannot INPUT into array 'a'"),
+            ("print y\nlet y = 1", "use of undeclared variable 'y'"),
+            (
+                "let s = \"a\"\nwhile s > 1 repeat\nendwhile",
+                "only == and != can compare strings",
+            ),
+            (
+                "let s = \"a\"\nif s == 1 then\nendif",
+                "cannot compare a string with an integer",
+            ),
+        ] {
+            assert_eq!(infer_source(input).unwrap_err().to_string(), message);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use std::process::Command;
+
+fn teeny(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_teeny_compiler"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_parser_is_quiet_by_default() {
+    let output = teeny(&["compile", "tinycode/hello.tiny"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stdout.contains("AST---"));
+    assert!(!stderr.contains("AST---"));
+
+    let output = teeny(&["compile", "tinycode/hello.tiny", "--verbose"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stdout.contains("AST---"));
+    assert!(stderr.contains("AST---"));
+}
+
+#[test]
+fn test_tokens_subcommand_lex_error() {
+    let path = write_source("tokens", "print @");
+    let output = teeny(&["tokens", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("line 1, col 7: unexpected character '@'"));
+}
+
+fn write_source(name: &str, source: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("teeny-{}-{}.tiny", name, std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn test_compile_failures_exit_non_zero() {
+    let output = teeny(&["compile", "tinycode/does-not-exist.tiny"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("error: could not read tinycode/does-not-exist.tiny"));
+
+    let path = write_source("lex-error", "print @");
+    let output = teeny(&["compile", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "error: line 1, col 7: unexpected character '@'\n");
+
+    let path = write_source("parse-error", "let = 1");
+    let output = teeny(&["compile", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "error: line 1: Expected identifier after LET\n");
+
+    // Errors found after parsing say where they are too
+    let path = write_source("type-error", "let x = 1\nlet x = \"hi\"\n");
+    let output = teeny(&["compile", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("error: line 2: "), "{}", stderr);
+}
+
+#[test]
+fn test_compile_multiple_files() {
+    let first = write_source("multi-a", "let x = 1\ngoto second\nlabel first\nprint x\n");
+    let second = write_source("multi-b", "label second\nlet x = x + 1\ngoto first\n");
+    let out = std::env::temp_dir().join(format!("teeny-multi-{}.c", std::process::id()));
+    let output = teeny(&[
+        "compile",
+        first.to_str().unwrap(),
+        second.to_str().unwrap(),
+        "-o",
+        out.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let source = std::fs::read_to_string(&out).unwrap();
+    let (header, source) = source.split_once('\n').unwrap();
+    assert_eq!(
+        header,
+        format!(
+            "/* Generated by teeny compiler v{} from {}, {} */",
+            env!("CARGO_PKG_VERSION"),
+            first.display(),
+            second.display()
+        )
+    );
+    assert_eq!(
+        source,
+        "#include <stdio.h>\nint main(void){\n\n    int x;\n    x = 1;\n    goto second;\n    first:;\n\
+         \x20   printf(\"%d\\n\", x);\n    second:;\n    x = x + 1;\n    goto first;\n    return 0;\n}\n"
+    );
+    std::fs::remove_file(&out).unwrap();
+
+    let clash = write_source("multi-c", "label first\n");
+    let output = teeny(&["compile", first.to_str().unwrap(), clash.to_str().unwrap()]);
+    for path in [first, second, clash.clone()] {
+        std::fs::remove_file(path).unwrap();
+    }
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("error: label 'first' is defined in both"));
+    assert!(stderr.trim_end().ends_with(clash.to_str().unwrap()));
+}
+
+#[test]
+fn test_compile_and_run() {
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    if Command::new(&cc).arg("--version").output().is_err() {
+        eprintln!("skipping: no C compiler found");
+        return;
+    }
+    let path = write_source("run", "let x = 6\nprint x * 7\nprint \"done\"\n");
+    let output = teeny(&["compile", path.to_str().unwrap(), "--run"]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("42\ndone\n"));
+
+    let path = write_source("run-no-cc", "print 1\n");
+    let output = Command::new(env!("CARGO_BIN_EXE_teeny_compiler"))
+        .args(["compile", path.to_str().unwrap(), "--run"])
+        .env("CC", "teeny-no-such-compiler")
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("error: could not find a C compiler 'teeny-no-such-compiler'"));
+}
+
+#[test]
+fn test_fmt_subcommand() {
+    let formatted = "LET x = 1\nIF x == 1 THEN\n  PRINT x\nENDIF\n";
+    let path = write_source("fmt", "let x=1\nif x==1 then\nprint x\nendif");
+    let output = teeny(&["fmt", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), formatted);
+
+    let output = teeny(&["fmt", "--write", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), formatted);
+
+    // Formatting an already formatted file leaves it as it is
+    let output = teeny(&["fmt", "--write", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), formatted);
+    std::fs::remove_file(&path).unwrap();
+
+    let path = write_source("fmt-error", "let x=\n");
+    let output = teeny(&["fmt", "--write", path.to_str().unwrap()]);
+    let source = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("error: "));
+    assert_eq!(source, "let x=\n");
+}
+
+#[test]
+fn test_check_subcommand() {
+    let path = write_source("check", "let x = 1\nprint x\n");
+    let output = teeny(&["check", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    let path = write_source("check-error", "let x = 1\nprint y\n");
+    let output = teeny(&["check", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: line 2: use of undeclared variable 'y'\n"
+    );
+}
+
+#[test]
+fn test_compile_provenance_comment() {
+    let out = std::env::temp_dir().join(format!("teeny-provenance-{}.c", std::process::id()));
+    let output = teeny(&[
+        "compile",
+        "tinycode/hello.tiny",
+        "-o",
+        out.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let source = std::fs::read_to_string(&out).unwrap();
+    std::fs::remove_file(&out).unwrap();
+    let lines: Vec<&str> = source.lines().collect();
+    assert_eq!(
+        lines[0],
+        format!(
+            "/* Generated by teeny compiler v{} from tinycode/hello.tiny */",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+    assert_eq!(lines[1], "#include <stdio.h>");
+}
+
+#[test]
+fn test_compile_emit_stages() {
+    let output = teeny(&["compile", "tinycode/hello.tiny", "--emit", "tokens"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>()[..2],
+        ["   0: Print", "   1: String { value: \"hello world\" }"]
+    );
+
+    let output = teeny(&["compile", "tinycode/hello.tiny", "--emit", "ast"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("PrintString \"hello world\""));
+    assert!(!stdout.contains("printf"));
+
+    // Only the C goes to stdout, so it can be piped straight into a file
+    for args in [
+        &["compile", "tinycode/hello.tiny"][..],
+        &["compile", "tinycode/hello.tiny", "--emit", "c"],
+    ] {
+        let output = teeny(args);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!(
+                "/* Generated by teeny compiler v{} from tinycode/hello.tiny */\n\
+                 #include <stdio.h>\n\
+                 int main(void){{\n\n    \
+                 printf(\"hello world\\n\");\n    \
+                 return 0;\n\
+                 }}\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    let output = teeny(&["compile", "tinycode/hello.tiny", "--emit", "ast", "--run"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "error: --run needs --emit c\n");
+
+    let output = teeny(&["compile", "tinycode/hello.tiny", "--emit", "llvm"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_compile_int_type() {
+    let path = write_source("int-type", "let x = 5\nprint x * 2\n");
+    let output = teeny(&["compile", path.to_str().unwrap(), "--int-type", "long"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(stdout.contains("long x;"));
+    assert!(stdout.contains("printf(\"%ld\\n\", x * 2);"));
+
+    let output = teeny(&["compile", path.to_str().unwrap(), "--int-type", "short"]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown integer type 'short', expected int, long or int64_t"));
+}
+
+#[test]
+fn test_compile_checked_arith_optimize() {
+    let path = write_source("checked-optimize", "print 2147483647 + 1\n");
+    let output = teeny(&[
+        "compile",
+        path.to_str().unwrap(),
+        "--checked-arith",
+        "--optimize",
+    ]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("printf(\"%d\\n\", teeny_add(2147483647, 1));"));
+    assert!(!stdout.contains("-2147483648"));
+}
+
+#[test]
+fn test_compile_dir() {
+    let dir = std::env::temp_dir().join(format!("teeny-dir-{}", std::process::id()));
+    let out_dir = dir.join("out");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("one.tiny"), "print 1").unwrap();
+    std::fs::write(dir.join("two.tiny"), "print \"two\"").unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a program").unwrap();
+
+    let output = teeny(&[
+        "compile-dir",
+        dir.to_str().unwrap(),
+        out_dir.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert!(out_dir.join("one.c").exists());
+    assert!(out_dir.join("two.c").exists());
+    assert!(!out_dir.join("notes.c").exists());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("Compiled 2 of 2 files\n"));
+
+    // One bad file is reported without stopping the others
+    std::fs::write(dir.join("bad.tiny"), "print @").unwrap();
+    let output = teeny(&[
+        "compile-dir",
+        dir.to_str().unwrap(),
+        out_dir.to_str().unwrap(),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stdout.ends_with("Compiled 2 of 3 files\n"));
+    assert!(stderr.contains("bad.tiny: line 1, col 7: unexpected character '@'"));
+    assert!(stderr.ends_with("error: 1 of 3 files failed to compile\n"));
+}
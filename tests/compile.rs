@@ -0,0 +1,99 @@
+use teeny_compiler::{compile, compile_str};
+
+#[test]
+fn test_compile() {
+    let source =
+        "let x = 3\nwhile x > 0 repeat\nprint x\nlet x = x - 1\nendwhile\nprint \"done\"\n";
+    assert_eq!(
+        compile(source).unwrap(),
+        r#"#include <stdio.h>
+int main(void){
+
+    int x;
+    x = 3;
+    while (x > 0) {
+        printf("%d\n", x);
+        x = x - 1;
+    }
+    printf("done\n");
+    return 0;
+}
+"#
+    );
+}
+
+#[test]
+fn test_compile_error() {
+    assert!(compile("let = 1").is_err());
+}
+
+#[test]
+fn test_compile_str() {
+    assert_eq!(
+        compile_str("let x = 1 + 2\nprint x\n").unwrap(),
+        [
+            "#include <stdio.h>",
+            "int main(void){",
+            "",
+            "    int x;",
+            "    x = 1 + 2;",
+            "    printf(\"%d\\n\", x);",
+            "    return 0;",
+            "}",
+        ]
+    );
+    assert!(compile_str("print y").is_err());
+}
+
+#[test]
+fn test_compile_left_associative() {
+    // C groups these the same way, so no brackets are needed unless the
+    // source has them
+    let lines = compile_str(
+        "print 10 - 2 - 3\nprint 100 / 10 / 2\nprint 10 - (2 - 3)\nprint 100 / (10 / 2)\n",
+    )
+    .unwrap();
+    assert_eq!(
+        lines[3..7],
+        [
+            "    printf(\"%d\\n\", 10 - 2 - 3);",
+            "    printf(\"%d\\n\", 100 / 10 / 2);",
+            "    printf(\"%d\\n\", 10 - (2 - 3));",
+            "    printf(\"%d\\n\", 100 / (10 / 2));",
+        ]
+    );
+}
+
+#[test]
+fn test_compile_compound_comparison() {
+    let lines = compile_str(
+        "let x = 4\nlet y = 20\nif x * 2 + 1 < y - 3 then\nprint (x + 1) * 2 >= y / 4 % 3\nendif\n",
+    )
+    .unwrap();
+    assert_eq!(lines[7], "    if (x * 2 + 1 < y - 3) {");
+    assert_eq!(
+        lines[8],
+        "        printf(\"%d\\n\", ((x + 1) * 2 >= y / 4 % 3));"
+    );
+}
+
+#[test]
+fn test_compile_long_chains() {
+    // Long chains of one operator are flat lists rather than nested, so they
+    // compile without running any pass out of stack
+    let bit_or = format!("print {}\n", vec!["1"; 20_000].join(" | "));
+    let and = format!("let x = {}\n", vec!["1 < 2"; 5_000].join(" and "));
+    for source in [bit_or, and] {
+        assert!(compile(&source).is_ok());
+    }
+
+    // Anything that really nests is cut off with an error instead
+    let nots = format!("let x = {}1 < 2\n", "not ".repeat(5_000));
+    let groups = format!("print {}1{}\n", "(1 | ".repeat(5_000), ")".repeat(5_000));
+    for source in [nots, groups] {
+        assert_eq!(
+            compile(&source).unwrap_err().to_string(),
+            "nesting too deep"
+        );
+    }
+}